@@ -1,29 +1,55 @@
-//! Used for common cli components
+//! Used for common cli components.
+//!
+//! Flags are only the top layer of the configuration, so every flag is optional and only
+//! serialized when it's given. See [`config`](crate::config) for the defaults.
 
-use clap::{ArgAction, Args, Parser};
-use openssl::error::ErrorStack;
-use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslContext, SslFiletype, SslMethod};
-use openssl::x509::X509;
+use crate::config::LogFormat;
+use clap::Parser;
+use serde::Serialize;
 use std::path::PathBuf;
 
+/// Where the configuration comes from
 #[derive(Debug, Parser)]
+pub struct ConfigArgs {
+    /// A TOML configuration file. Environment variables and flags take precedence over it.
+    #[clap(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Prints the merged configuration, with secrets redacted, then exits
+    #[clap(long, global = true)]
+    pub print_config: bool,
+}
+
+#[derive(Debug, Parser, Serialize)]
 pub struct LoggingArgs {
-    /// How verbose logging should be.
-    #[clap(short = 'v')]
-    #[clap(action = ArgAction::Count)]
-    #[clap(value_parser = clap::value_parser!(u8).range(0..3))]
-    pub verbosity: u8,
+    /// Filter directives, such as `info,users_service=debug`. `RUST_LOG` is used if not set
+    /// anywhere else. [default: info]
+    #[clap(long = "log")]
+    #[serde(rename = "filter", skip_serializing_if = "Option::is_none")]
+    pub log_filter: Option<String>,
+    /// How logs are written [default: text]
+    #[clap(long)]
+    #[serde(rename = "format", skip_serializing_if = "Option::is_none")]
+    pub log_format: Option<LogFormat>,
+    /// An OTLP/HTTP collector that spans are exported to. `OTEL_EXPORTER_OTLP_ENDPOINT` is used
+    /// if not set anywhere else.
+    #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otlp_endpoint: Option<String>,
 }
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Parser, Serialize)]
 pub struct WebServerArgs {
-    #[clap(long, default_value = "localhost")]
-    pub ip: String,
-    #[clap(long, default_value_t = 8080)]
-    pub port: u16,
+    /// The address to listen on [default: localhost]
+    #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    /// The port to listen on [default: 8080]
+    #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
 }
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Parser, Serialize)]
 pub struct CommonArgs {
     /// Contains the logging args
     #[clap(flatten)]
@@ -33,52 +59,63 @@ pub struct CommonArgs {
     pub server: WebServerArgs,
 }
 
+/// Database connection, pool tuning, and how hard to try connecting at startup
+#[derive(Debug, Clone, Parser, Serialize)]
+pub struct DatabaseArgs {
+    /// The url of the primary database. `DATABASE_URL` is used if not set anywhere else.
+    #[clap(long = "database-url")]
+    #[serde(rename = "url", skip_serializing_if = "Option::is_none")]
+    pub db_url: Option<String>,
+    /// The url of a read replica. `DATABASE_READ_URL` is used if not set anywhere else.
+    #[clap(long = "database-read-url")]
+    #[serde(rename = "read_url", skip_serializing_if = "Option::is_none")]
+    pub db_read_url: Option<String>,
+    /// The most connections the pool keeps open [default: 10]
+    #[clap(long)]
+    #[serde(rename = "pool_size", skip_serializing_if = "Option::is_none")]
+    pub db_pool_size: Option<u32>,
+    /// The fewest idle connections the pool keeps open. Defaults to the pool size.
+    #[clap(long)]
+    #[serde(rename = "min_idle", skip_serializing_if = "Option::is_none")]
+    pub db_min_idle: Option<u32>,
+    /// Seconds to wait for a connection before giving up [default: 30]
+    #[clap(long)]
+    #[serde(rename = "connection_timeout", skip_serializing_if = "Option::is_none")]
+    pub db_connection_timeout: Option<u64>,
+    /// Seconds before an idle connection is closed [default: 600]
+    #[clap(long)]
+    #[serde(rename = "idle_timeout", skip_serializing_if = "Option::is_none")]
+    pub db_idle_timeout: Option<u64>,
+    /// How many times to try connecting at startup. Retries forever if not set.
+    #[clap(long)]
+    #[serde(rename = "connect_attempts", skip_serializing_if = "Option::is_none")]
+    pub db_connect_attempts: Option<u32>,
+    /// The longest wait, in seconds, between two connection attempts at startup [default: 30]
+    #[clap(long)]
+    #[serde(rename = "max_backoff", skip_serializing_if = "Option::is_none")]
+    pub db_max_backoff: Option<u64>,
+}
+
 /// Used for security purposes, usually for TLS.
-#[derive(Debug, Parser)]
+#[derive(Debug, Parser, Serialize)]
 pub struct SecurityArgs {
     /// Path to a certificate (public key)
     #[clap(long)]
-    #[clap(requires("cert_key"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
     cert: Option<PathBuf>,
     /// Path to a private key.
     ///
     /// Required if --cert is specified.
     #[clap(long)]
     #[clap(alias("key"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
     cert_key: Option<PathBuf>,
     /// Path to a root authority
     #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
     capath: Option<PathBuf>,
-}
-
-impl SecurityArgs {
-    /// Creates an ssl context from the security args
-    pub fn ssl_acceptor(&self) -> Result<SslAcceptorBuilder, SecurityBuilderError> {
-        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
-        if let Some(cert) = &self.cert {
-            let cery_key = self
-                .cert_key
-                .as_ref()
-                .expect("will always be present if cert is present");
-
-            builder.set_certificate_file(cert, SslFiletype::PEM)?;
-            builder.set_private_key_file(cery_key, SslFiletype::PEM)?;
-        } else {
-            return Err(SecurityBuilderError::NotPresent);
-        }
-
-        if let Some(ca) = &self.capath {
-            builder.set_ca_file(ca)?;
-        }
-
-        Ok(builder)
-    }
-}
-
-#[derive(Debug, thiserror::Error)]
-pub enum SecurityBuilderError {
-    #[error("No ssl context was built")]
-    NotPresent,
-    #[error(transparent)]
-    OpensslError(#[from] ErrorStack),
+    /// Rejects clients without a certificate signed by --capath
+    #[clap(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    require_client_cert: bool,
 }
@@ -0,0 +1,156 @@
+//! Liveness and readiness endpoints shared by every service.
+//!
+//! Give the app a `Data<Health>` holding the checks of the service, then register the
+//! endpoints with [`configure`].
+
+use actix_web::http::StatusCode;
+use actix_web::web::{self, Data, ServiceConfig};
+use actix_web::HttpResponse;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Something a service needs in order to handle requests, like its database
+pub trait HealthCheck: Debug + Send + Sync {
+    /// The name the check is reported under
+    fn name(&self) -> &str;
+
+    /// Checks if this is healthy, returning why not otherwise. May block.
+    fn check(&self) -> Result<(), String>;
+}
+
+/// The status of a single check, or of all of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Unavailable,
+}
+
+/// The result of a single check
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckReport {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The results of every check
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub status: Status,
+    pub checks: BTreeMap<String, CheckReport>,
+}
+
+/// The checks of a service
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    checks: Vec<Arc<dyn HealthCheck>>,
+}
+
+impl Health {
+    /// Creates a health with no checks, which is always ready
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a check
+    pub fn with_check<C: HealthCheck + 'static>(mut self, check: C) -> Self {
+        self.checks.push(Arc::new(check));
+        self
+    }
+
+    /// Runs every check
+    pub fn report(&self) -> HealthReport {
+        let checks: BTreeMap<_, _> = self
+            .checks
+            .iter()
+            .map(|check| {
+                let report = match check.check() {
+                    Ok(()) => CheckReport {
+                        status: Status::Ok,
+                        error: None,
+                    },
+                    Err(error) => CheckReport {
+                        status: Status::Unavailable,
+                        error: Some(error),
+                    },
+                };
+                (check.name().to_string(), report)
+            })
+            .collect();
+        let status = if checks.values().all(|c| c.status == Status::Ok) {
+            Status::Ok
+        } else {
+            Status::Unavailable
+        };
+        HealthReport { status, checks }
+    }
+}
+
+/// Registers `/healthz` and `/readyz`.
+///
+/// `/healthz` answers as long as the process can serve requests, while `/readyz` answers
+/// `503 Service Unavailable` if any check fails. Both include the report of every check.
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.route("/healthz", web::get().to(healthz))
+        .route("/readyz", web::get().to(readyz));
+}
+
+async fn healthz(health: Option<Data<Health>>) -> actix_web::Result<HttpResponse> {
+    let report = run_checks(health).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+async fn readyz(health: Option<Data<Health>>) -> actix_web::Result<HttpResponse> {
+    let report = run_checks(health).await?;
+    let status = match report.status {
+        Status::Ok => StatusCode::OK,
+        Status::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    Ok(HttpResponse::build(status).json(report))
+}
+
+async fn run_checks(health: Option<Data<Health>>) -> actix_web::Result<HealthReport> {
+    let health = health.map(|h| h.into_inner()).unwrap_or_default();
+    Ok(web::block(move || health.report()).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    #[derive(Debug)]
+    struct Failing;
+
+    impl HealthCheck for Failing {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn check(&self) -> Result<(), String> {
+            Err("down".to_string())
+        }
+    }
+
+    #[actix_web::test]
+    async fn ready_only_when_all_checks_pass() {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(Health::new().with_check(Failing)))
+                .configure(configure),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/healthz").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get().uri("/readyz").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["checks"]["failing"]["error"], "down");
+    }
+}
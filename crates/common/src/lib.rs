@@ -1,7 +1,14 @@
 //! Common utilities for democratic forum
 
 pub mod cli;
+pub mod config;
 pub mod error_responder;
+pub mod health;
 pub mod logging;
+pub mod metrics;
 pub mod repo;
+pub mod retry;
+pub mod secret;
+pub mod shutdown;
+pub mod trace_context;
 pub mod utils;
\ No newline at end of file
@@ -1,28 +1,148 @@
-use crate::cli::LoggingArgs;
-use log::LevelFilter;
-use log4rs::append::console::ConsoleAppender;
-use log4rs::config::{Appender, Root};
-use log4rs::encode::pattern::PatternEncoder;
-use log4rs::Config;
-
-static LOG_ENCODING: &str =
-    r#"{d(%Y-%m-%d %H:%M:%S%.3f)} [{h({l:7})}] \({({M}:{L}):>32.48}\) - {m}{n}"#;
-
-/// initialize logging for a binary.
-pub fn init_logging(logging_args: &LoggingArgs) {
-    let filter = match logging_args.verbosity {
-        0 => LevelFilter::Info,
-        1 => LevelFilter::Debug,
-        2 => LevelFilter::Trace,
-        v => panic!("unsupported verbosity {}", v),
+//! Logging and tracing shared by every binary.
+//!
+//! Everything goes through `tracing`. Records from the `log` crate, such as the ones of the actix
+//! `Logger` middleware, are forwarded to it.
+
+use crate::config::{LogFormat, LoggingConfig};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{self, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing_subscriber::filter::ParseError;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::{SubscriberInitExt, TryInitError};
+use tracing_subscriber::{fmt, EnvFilter, Layer, Registry};
+
+/// Logging could not be initialized
+#[derive(Debug, thiserror::Error)]
+pub enum LoggingError {
+    #[error(transparent)]
+    Filter(#[from] ParseError),
+    #[error("could not create the OTLP exporter: {0}")]
+    Otlp(#[from] opentelemetry::trace::TraceError),
+    #[error(transparent)]
+    Init(#[from] TryInitError),
+}
+
+/// initialize logging for a binary, exporting spans as `service` if an OTLP endpoint is set.
+pub fn init_logging(logging: &LoggingConfig, service: &str) -> Result<(), LoggingError> {
+    let filter = EnvFilter::try_new(&logging.filter)?;
+    let stdout: Box<dyn Layer<Registry> + Send + Sync> = match logging.format {
+        LogFormat::Text => fmt::layer().boxed(),
+        LogFormat::Json => fmt::layer().json().flatten_event(true).boxed(),
     };
-    let stdout = ConsoleAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(LOG_ENCODING)))
-        .build();
-    let config = Config::builder()
-        .appender(Appender::builder().build("stdout", Box::new(stdout)))
-        .build(Root::builder().appender("stdout").build(filter))
-        .unwrap();
-
-    log4rs::init_config(config).unwrap();
+    let otlp = logging
+        .otlp_endpoint
+        .as_deref()
+        .map(|endpoint| otlp_tracer(endpoint, service))
+        .transpose()?
+        .map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
+
+    tracing_subscriber::registry()
+        .with(stdout)
+        .with(otlp)
+        .with(filter)
+        .try_init()?;
+    Ok(())
+}
+
+/// Creates a tracer that exports spans in batches to an OTLP/HTTP collector.
+///
+/// Must be called from within a Tokio runtime.
+fn otlp_tracer(endpoint: &str, service: &str) -> Result<Tracer, LoggingError> {
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(endpoint);
+    let config = trace::config().with_resource(Resource::new([KeyValue::new(
+        "service.name",
+        service.to_string(),
+    )]));
+
+    Ok(opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(config)
+        .install_batch(runtime::TokioCurrentThread)?)
+}
+
+/// Exports the spans that have not been exported yet. Nothing is exported afterwards.
+pub fn flush() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use tracing::info_span;
+
+    /// Accepts OTLP/HTTP requests, sending the path and body of each one
+    fn collector() -> (String, mpsc::Receiver<(String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let path = line.split(' ').nth(1).unwrap_or_default().to_string();
+
+                let mut length = 0;
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                    .unwrap();
+                let _ = sender.send((path, body));
+            }
+        });
+        (endpoint, receiver)
+    }
+
+    #[tokio::test]
+    async fn spans_are_exported_to_the_collector() {
+        let (endpoint, received) = collector();
+        let tracer = otlp_tracer(&endpoint, "logging-test").unwrap();
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        tracing::subscriber::with_default(subscriber, || {
+            info_span!("exported_span").in_scope(|| {});
+        });
+        flush();
+
+        let (path, body) = received.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(path, "/v1/traces");
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("exported_span"), "{body}");
+        assert!(body.contains("logging-test"), "{body}");
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        let logging = LoggingConfig {
+            filter: "users_service=loud".to_string(),
+            ..LoggingConfig::default()
+        };
+        assert!(matches!(
+            init_logging(&logging, "test"),
+            Err(LoggingError::Filter(_))
+        ));
+    }
 }
@@ -0,0 +1,176 @@
+//! Prometheus metrics shared by every service.
+//!
+//! Everything is registered in the [default registry](prometheus::default_registry), which is
+//! served by [`configure`]. Wrap the app in [`RequestMetrics`] to record every request.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::ContentType;
+use actix_web::web::{self, ServiceConfig};
+use actix_web::HttpResponse;
+use futures_util::future::LocalBoxFuture;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, TextEncoder,
+};
+use std::future::{ready, Ready};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+/// Creates and registers a counter with the given labels
+///
+/// # Panics
+/// Panics if a metric with the same name is already registered.
+pub fn counter_vec(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels).expect("invalid counter");
+    prometheus::register(Box::new(counter.clone())).expect("counter registered twice");
+    counter
+}
+
+/// Creates and registers a gauge with the given labels
+///
+/// # Panics
+/// Panics if a metric with the same name is already registered.
+pub fn gauge_vec(name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    let gauge = IntGaugeVec::new(Opts::new(name, help), labels).expect("invalid gauge");
+    prometheus::register(Box::new(gauge.clone())).expect("gauge registered twice");
+    gauge
+}
+
+/// Registers `/metrics`, which serves every registered metric in the Prometheus text format
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.route("/metrics", web::get().to(metrics));
+}
+
+async fn metrics() -> actix_web::Result<HttpResponse> {
+    let mut body = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut body)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(body))
+}
+
+struct HttpMetrics {
+    requests: IntCounterVec,
+    duration: HistogramVec,
+}
+
+fn http_metrics() -> &'static HttpMetrics {
+    static METRICS: OnceLock<HttpMetrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "How long requests took to handle",
+            ),
+            &["method", "path"],
+        )
+        .expect("invalid histogram");
+        prometheus::register(Box::new(duration.clone())).expect("histogram registered twice");
+        HttpMetrics {
+            requests: counter_vec(
+                "http_requests_total",
+                "Requests handled, by response status",
+                &["method", "path", "status"],
+            ),
+            duration,
+        }
+    })
+}
+
+/// Middleware recording the count and duration of requests.
+///
+/// Requests are labelled by the route pattern that matched them, not by their path, so ids in
+/// paths don't create a new series each.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware { service }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        let path = req
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+        let response = self.service.call(req);
+
+        Box::pin(async move {
+            let response = response.await;
+            let status = match &response {
+                Ok(response) => response.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            let metrics = http_metrics();
+            metrics
+                .requests
+                .with_label_values(&[&method, &path, status.as_str()])
+                .inc();
+            metrics
+                .duration
+                .with_label_values(&[&method, &path])
+                .observe(start.elapsed().as_secs_f64());
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn records_requests_by_route() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestMetrics)
+                .configure(configure)
+                .route("/items/{id}", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/items/42").to_request();
+        test::call_service(&app, req).await;
+
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(
+            body.contains(r#"http_requests_total{method="GET",path="/items/{id}",status="200"} 1"#),
+            "{body}"
+        );
+    }
+}
@@ -1,28 +1,734 @@
 //! A repo trait, for convenience
 
+use async_trait::async_trait;
+use diesel::r2d2::{ManageConnection, Pool, PooledConnection};
 use diesel::Identifiable;
+use std::marker::PhantomData;
+
+/// The result of a repository operation
+pub type RepositoryResult<T> = Result<T, RepositoryError>;
+
+/// An error occurred within a repository
+#[derive(Debug, thiserror::Error)]
+pub enum RepositoryError {
+    #[error("can not sort by unknown property {0:?}")]
+    UnknownProperty(String),
+    #[error(transparent)]
+    Database(#[from] diesel::result::Error),
+    #[error(transparent)]
+    Pool(#[from] diesel::r2d2::PoolError),
+}
 
 /// A crud repository
 pub trait Repository<T, Id>
 where
     for<'a> &'a T: Identifiable<Id = &'a Id>,
 {
+    type Error;
+
+    /// Gets the number of entities available
+    fn count(&mut self) -> Result<u64, Self::Error>;
+    /// Checks whether an entity with the given id exists
+    fn exists_by_id(&mut self, id: Id) -> Result<bool, Self::Error>;
+
+    /// Deletes an object from the repository
+    fn delete(&mut self, obj: T) -> Result<(), Self::Error>;
+    fn delete_all(&mut self) -> Result<(), Self::Error>;
+    fn delete_iter<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<(), Self::Error>;
+    fn delete_by_ids<I: IntoIterator<Item = Id>>(&mut self, iter: I) -> Result<(), Self::Error>;
+    fn delete_by_id(&mut self, id: Id) -> Result<(), Self::Error>;
+
+    /// Finds a single page of entities
+    fn find_all(&mut self, pageable: &Pageable<Id>) -> Result<Page<T, Id>, Self::Error>;
+    fn find_all_by_id<I: IntoIterator<Item = Id>>(&mut self, iter: I) -> Result<Vec<T>, Self::Error>;
+
+    /// Finds an entity by an id
+    fn find_by_id(&mut self, id: Id) -> Result<Option<T>, Self::Error>;
+
+    /// Stores the object, returning it as it was stored
+    fn save(&mut self, obj: T) -> Result<T, Self::Error>;
+    fn save_all<I: IntoIterator<Item = T>>(&mut self, obj: I) -> Result<Vec<T>, Self::Error>;
+}
+
+/// The async version of a [`Repository`], which can be used directly from request handlers.
+#[async_trait]
+pub trait AsyncRepository<T, Id>
+where
+    for<'a> &'a T: Identifiable<Id = &'a Id>,
+{
+    type Error;
+
     /// Gets the number of entities available
-    fn count(&mut self) -> u64;
+    async fn count(&self) -> Result<u64, Self::Error>;
+    /// Checks whether an entity with the given id exists
+    async fn exists_by_id(&self, id: Id) -> Result<bool, Self::Error>;
+
     /// Deletes an object from the repository
-    fn delete(&mut self, obj: T);
-    fn delete_all(&mut self);
-    fn delete_iter<I: IntoIterator<Item = T>>(&mut self, iter: I);
-    fn delete_by_ids<I: IntoIterator<Item = Id>>(&mut self, iter: I);
-    fn delete_by_id(&mut self, iter: Id);
+    async fn delete(&self, obj: T) -> Result<(), Self::Error>;
+    async fn delete_all(&self) -> Result<(), Self::Error>;
+    async fn delete_by_ids(&self, ids: Vec<Id>) -> Result<(), Self::Error>;
+    async fn delete_by_id(&self, id: Id) -> Result<(), Self::Error>;
 
-    fn find_all(&mut self) -> Vec<T>;
-    fn find_all_by_id<I: IntoIterator<Item = Id>>(&mut self, iter: I) -> Vec<T>;
+    /// Finds a single page of entities
+    async fn find_all(&self, pageable: Pageable<Id>) -> Result<Page<T, Id>, Self::Error>;
+    async fn find_all_by_id(&self, ids: Vec<Id>) -> Result<Vec<T>, Self::Error>;
 
     /// Finds an entity by an id
-    fn find_by_id(&mut self, id: Id) -> Option<T>;
+    async fn find_by_id(&self, id: Id) -> Result<Option<T>, Self::Error>;
+
+    /// Stores the object, returning it as it was stored
+    async fn save(&self, obj: T) -> Result<T, Self::Error>;
+    async fn save_all(&self, objs: Vec<T>) -> Result<Vec<T>, Self::Error>;
+}
+
+/// A repository that can be created from an owned connection
+pub trait FromConnection<C> {
+    fn from_connection(conn: C) -> Self;
+}
+
+/// Runs a blocking [`Repository`] on a connection pool, exposing it as an [`AsyncRepository`].
+///
+/// Every call takes a connection from the pool and runs on the blocking thread pool.
+pub struct PooledRepository<M: ManageConnection, R> {
+    pool: Pool<M>,
+    _repository: PhantomData<fn() -> R>,
+}
+
+impl<M: ManageConnection, R> PooledRepository<M, R> {
+    /// Creates a new pooled repository
+    pub fn new(pool: Pool<M>) -> Self {
+        Self {
+            pool,
+            _repository: PhantomData,
+        }
+    }
+}
+
+impl<M: ManageConnection, R> Clone for PooledRepository<M, R> {
+    fn clone(&self) -> Self {
+        Self::new(self.pool.clone())
+    }
+}
+
+impl<M, R> PooledRepository<M, R>
+where
+    M: ManageConnection,
+    R: FromConnection<PooledConnection<M>>,
+{
+    async fn run<O, E, F>(&self, f: F) -> Result<O, E>
+    where
+        F: FnOnce(&mut R) -> Result<O, E> + Send + 'static,
+        O: Send + 'static,
+        E: From<diesel::r2d2::PoolError> + Send + 'static,
+    {
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut repository = R::from_connection(pool.get()?);
+            f(&mut repository)
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+}
+
+#[async_trait]
+impl<T, Id, M, R> AsyncRepository<T, Id> for PooledRepository<M, R>
+where
+    for<'a> &'a T: Identifiable<Id = &'a Id>,
+    T: Send + 'static,
+    Id: Send + 'static,
+    M: ManageConnection,
+    R: Repository<T, Id> + FromConnection<PooledConnection<M>>,
+    R::Error: From<diesel::r2d2::PoolError> + Send + 'static,
+{
+    type Error = R::Error;
+
+    async fn count(&self) -> Result<u64, Self::Error> {
+        self.run(|repo| repo.count()).await
+    }
+
+    async fn exists_by_id(&self, id: Id) -> Result<bool, Self::Error> {
+        self.run(|repo| repo.exists_by_id(id)).await
+    }
+
+    async fn delete(&self, obj: T) -> Result<(), Self::Error> {
+        self.run(|repo| repo.delete(obj)).await
+    }
+
+    async fn delete_all(&self) -> Result<(), Self::Error> {
+        self.run(|repo| repo.delete_all()).await
+    }
+
+    async fn delete_by_ids(&self, ids: Vec<Id>) -> Result<(), Self::Error> {
+        self.run(|repo| repo.delete_by_ids(ids)).await
+    }
+
+    async fn delete_by_id(&self, id: Id) -> Result<(), Self::Error> {
+        self.run(|repo| repo.delete_by_id(id)).await
+    }
+
+    async fn find_all(&self, pageable: Pageable<Id>) -> Result<Page<T, Id>, Self::Error> {
+        self.run(move |repo| repo.find_all(&pageable)).await
+    }
+
+    async fn find_all_by_id(&self, ids: Vec<Id>) -> Result<Vec<T>, Self::Error> {
+        self.run(|repo| repo.find_all_by_id(ids)).await
+    }
+
+    async fn find_by_id(&self, id: Id) -> Result<Option<T>, Self::Error> {
+        self.run(|repo| repo.find_by_id(id)).await
+    }
+
+    async fn save(&self, obj: T) -> Result<T, Self::Error> {
+        self.run(|repo| repo.save(obj)).await
+    }
+
+    async fn save_all(&self, objs: Vec<T>) -> Result<Vec<T>, Self::Error> {
+        self.run(|repo| repo.save_all(objs)).await
+    }
+}
+
+/// The direction entities are sorted in
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum Direction {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Sorts by a single property
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Order {
+    property: String,
+    direction: Direction,
+}
+
+impl Order {
+    pub fn property(&self) -> &str {
+        &self.property
+    }
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+}
+
+/// How entities should be sorted. Earlier orders take precedence over later ones.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
+pub struct Sort {
+    orders: Vec<Order>,
+}
+
+impl Sort {
+    /// Entities are returned in id order
+    pub fn unsorted() -> Self {
+        Self::default()
+    }
+
+    /// Sorts by a property
+    pub fn by(property: impl Into<String>, direction: Direction) -> Self {
+        Self::unsorted().and(property, direction)
+    }
+
+    /// Sorts by another property, for entities that are equal by the previous orders
+    pub fn and(mut self, property: impl Into<String>, direction: Direction) -> Self {
+        self.orders.push(Order {
+            property: property.into(),
+            direction,
+        });
+        self
+    }
+
+    pub fn orders(&self) -> &[Order] {
+        &self.orders
+    }
+}
+
+/// Where a page starts
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Position<Id> {
+    /// Skips a number of entities
+    Offset(u64),
+    /// Starts right after the entity with the given id. Cursor pages are always ordered by id, so
+    /// they stay stable while entities are added or removed.
+    After(Id),
+}
+
+/// Requests a single page of entities
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Pageable<Id> {
+    position: Position<Id>,
+    size: u64,
+    sort: Sort,
+}
+
+impl<Id> Pageable<Id> {
+    /// The first page of a given size
+    pub fn first(size: u64) -> Self {
+        Self::offset(0, size)
+    }
+
+    /// A page of a given size, skipping `offset` entities
+    pub fn offset(offset: u64, size: u64) -> Self {
+        Self {
+            position: Position::Offset(offset),
+            size,
+            sort: Sort::unsorted(),
+        }
+    }
+
+    /// A page of a given size, starting after the entity with the given id
+    pub fn after(id: Id, size: u64) -> Self {
+        Self {
+            position: Position::After(id),
+            size,
+            sort: Sort::unsorted(),
+        }
+    }
+
+    /// Sorts the page. Ignored for cursor pages.
+    pub fn with_sort(mut self, sort: Sort) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    pub fn position(&self) -> &Position<Id> {
+        &self.position
+    }
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    pub fn sort(&self) -> &Sort {
+        &self.sort
+    }
+}
+
+/// A single page of entities
+#[derive(Debug, Clone)]
+pub struct Page<T, Id> {
+    content: Vec<T>,
+    total: u64,
+    next: Option<Pageable<Id>>,
+}
+
+impl<T, Id: Clone> Page<T, Id> {
+    /// Creates the page that was found for a request, working out where the next page starts
+    pub fn new(content: Vec<T>, total: u64, pageable: &Pageable<Id>) -> Self
+    where
+        for<'a> &'a T: Identifiable<Id = &'a Id>,
+    {
+        let next = match pageable.position() {
+            Position::Offset(offset) => {
+                let next_offset = offset + content.len() as u64;
+                (next_offset < total)
+                    .then(|| Pageable::offset(next_offset, pageable.size()))
+                    .map(|next| next.with_sort(pageable.sort().clone()))
+            }
+            Position::After(_) => content
+                .last()
+                .filter(|_| content.len() as u64 == pageable.size())
+                .map(|last| Pageable::after(last.id().clone(), pageable.size())),
+        };
+        Self {
+            content,
+            total,
+            next,
+        }
+    }
+}
+
+impl<T, Id> Page<T, Id> {
+    pub fn content(&self) -> &[T] {
+        &self.content
+    }
+    pub fn into_content(self) -> Vec<T> {
+        self.content
+    }
+    /// The total number of entities, across all pages
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+    /// The request for the next page, if there is one
+    pub fn next(&self) -> Option<&Pageable<Id>> {
+        self.next.as_ref()
+    }
+    pub fn is_last(&self) -> bool {
+        self.next.is_none()
+    }
+
+    /// Converts the content of this page, keeping its position
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U, Id> {
+        Page {
+            content: self.content.into_iter().map(f).collect(),
+            total: self.total,
+            next: self.next,
+        }
+    }
+}
+
+/// Creates a [`Repository`] backed by a diesel table.
+///
+/// The model must be [`Identifiable`], selectable from the table, and insertable into it. Entities
+/// are only inserted by [`save`](Repository::save) when no row with the same id exists yet, so
+/// tables with database generated ids should insert new rows themselves.
+///
+/// Saving an existing entity updates it with its `AsChangeset`, which by default skips `None`
+/// fields. Models with nullable columns should derive it with
+/// `#[diesel(treat_none_as_null = true)]`, so that saving `None` clears the column.
+///
+/// The repository can be created from anything that dereferences to the connection, such as a
+/// `&mut` reference or a pooled connection, and can be sorted by the listed columns.
+///
+/// ```ignore
+/// common::diesel_repository! {
+///     /// Stores users
+///     pub struct UserRepository<MysqlConnection>: User, i64 => crate::schema::user,
+///         sort: [email, username];
+/// }
+///
+/// let mut users = UserRepository::new(&mut conn);
+/// let user = users.find_by_id(1)?;
+/// let page = users.find_all(&Pageable::first(20).with_sort(Sort::by("email", Direction::Asc)))?;
+/// ```
+#[macro_export]
+macro_rules! diesel_repository {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident<$conn:ty>: $model:ty, $id:ty => $($schema:ident)::+
+        $(, sort: [$($sort:ident),* $(,)?])?;
+    ) => {
+        $(#[$meta])*
+        $vis struct $name<C> {
+            conn: C,
+        }
+
+        impl<C: ::std::ops::DerefMut<Target = $conn>> $name<C> {
+            /// Creates a repository using the given connection
+            $vis fn new(conn: C) -> Self {
+                Self { conn }
+            }
+
+            /// Gets the connection used by this repository
+            #[allow(dead_code)]
+            $vis fn connection(&mut self) -> &mut $conn {
+                &mut self.conn
+            }
+        }
+
+        impl<C: ::std::ops::DerefMut<Target = $conn>> $crate::repo::FromConnection<C> for $name<C> {
+            fn from_connection(conn: C) -> Self {
+                Self::new(conn)
+            }
+        }
+
+        impl<C: ::std::ops::DerefMut<Target = $conn>> $crate::repo::Repository<$model, $id>
+            for $name<C>
+        {
+            type Error = $crate::repo::RepositoryError;
+
+            fn count(&mut self) -> $crate::repo::RepositoryResult<u64> {
+                use ::diesel::prelude::*;
+                let count = $($schema)::+::table
+                    .count()
+                    .get_result::<i64>(self.connection())?;
+                Ok(count as u64)
+            }
+
+            fn exists_by_id(&mut self, id: $id) -> $crate::repo::RepositoryResult<bool> {
+                use ::diesel::prelude::*;
+                let exists = ::diesel::select(::diesel::dsl::exists($($schema)::+::table.find(id)))
+                    .get_result(self.connection())?;
+                Ok(exists)
+            }
+
+            fn delete(&mut self, obj: $model) -> $crate::repo::RepositoryResult<()> {
+                let id = ::diesel::Identifiable::id(&obj).clone();
+                self.delete_by_id(id)
+            }
+
+            fn delete_all(&mut self) -> $crate::repo::RepositoryResult<()> {
+                use ::diesel::prelude::*;
+                ::diesel::delete($($schema)::+::table).execute(self.connection())?;
+                Ok(())
+            }
+
+            fn delete_iter<I: IntoIterator<Item = $model>>(
+                &mut self,
+                iter: I,
+            ) -> $crate::repo::RepositoryResult<()> {
+                let ids = iter
+                    .into_iter()
+                    .map(|obj| ::diesel::Identifiable::id(&obj).clone())
+                    .collect::<Vec<$id>>();
+                self.delete_by_ids(ids)
+            }
+
+            fn delete_by_ids<I: IntoIterator<Item = $id>>(
+                &mut self,
+                iter: I,
+            ) -> $crate::repo::RepositoryResult<()> {
+                use ::diesel::prelude::*;
+                let ids = iter.into_iter().collect::<Vec<$id>>();
+                let table = $($schema)::+::table;
+                ::diesel::delete(table.filter(table.primary_key().eq_any(ids)))
+                    .execute(self.connection())?;
+                Ok(())
+            }
+
+            fn delete_by_id(&mut self, id: $id) -> $crate::repo::RepositoryResult<()> {
+                use ::diesel::prelude::*;
+                ::diesel::delete($($schema)::+::table.find(id)).execute(self.connection())?;
+                Ok(())
+            }
+
+            fn find_all(
+                &mut self,
+                pageable: &$crate::repo::Pageable<$id>,
+            ) -> $crate::repo::RepositoryResult<$crate::repo::Page<$model, $id>> {
+                use ::diesel::prelude::*;
+                use $crate::repo::{Direction, Position};
+                use $($schema)::+ as schema;
+
+                let table = schema::table;
+                let mut query = table
+                    .select(<$model>::as_select())
+                    .limit(pageable.size() as i64)
+                    .into_boxed::<<$conn as ::diesel::Connection>::Backend>();
+                match pageable.position() {
+                    Position::Offset(offset) => {
+                        for order in pageable.sort().orders() {
+                            query = match (order.property(), order.direction()) {
+                                $($(
+                                    (stringify!($sort), Direction::Asc) => {
+                                        query.then_order_by(schema::$sort.asc())
+                                    }
+                                    (stringify!($sort), Direction::Desc) => {
+                                        query.then_order_by(schema::$sort.desc())
+                                    }
+                                )*)?
+                                (property, _) => {
+                                    return Err($crate::repo::RepositoryError::UnknownProperty(
+                                        property.to_string(),
+                                    ))
+                                }
+                            };
+                        }
+                        query = query
+                            .then_order_by(table.primary_key().asc())
+                            .offset(*offset as i64);
+                    }
+                    Position::After(id) => {
+                        query = query
+                            .filter(table.primary_key().gt(id.clone()))
+                            .order_by(table.primary_key().asc());
+                    }
+                }
+
+                let content = query.load(self.connection())?;
+                let total = self.count()?;
+                Ok($crate::repo::Page::new(content, total, pageable))
+            }
+
+            fn find_all_by_id<I: IntoIterator<Item = $id>>(
+                &mut self,
+                iter: I,
+            ) -> $crate::repo::RepositoryResult<Vec<$model>> {
+                use ::diesel::prelude::*;
+                let ids = iter.into_iter().collect::<Vec<$id>>();
+                let table = $($schema)::+::table;
+                let found = table
+                    .filter(table.primary_key().eq_any(ids))
+                    .select(<$model>::as_select())
+                    .load(self.connection())?;
+                Ok(found)
+            }
+
+            fn find_by_id(&mut self, id: $id) -> $crate::repo::RepositoryResult<Option<$model>> {
+                use ::diesel::prelude::*;
+                let found = $($schema)::+::table
+                    .find(id)
+                    .select(<$model>::as_select())
+                    .first(self.connection())
+                    .optional()?;
+                Ok(found)
+            }
+
+            fn save(&mut self, obj: $model) -> $crate::repo::RepositoryResult<$model> {
+                use ::diesel::prelude::*;
+                let id = ::diesel::Identifiable::id(&obj).clone();
+                if self.exists_by_id(id.clone())? {
+                    ::diesel::update($($schema)::+::table.find(id))
+                        .set(&obj)
+                        .execute(self.connection())?;
+                } else {
+                    ::diesel::insert_into($($schema)::+::table)
+                        .values(&obj)
+                        .execute(self.connection())?;
+                }
+                Ok(obj)
+            }
+
+            fn save_all<I: IntoIterator<Item = $model>>(
+                &mut self,
+                obj: I,
+            ) -> $crate::repo::RepositoryResult<Vec<$model>> {
+                obj.into_iter().map(|obj| self.save(obj)).collect()
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::connection::SimpleConnection;
+    use diesel::prelude::*;
+    use diesel::r2d2::ConnectionManager;
+    use diesel::SqliteConnection;
+
+    diesel::table! {
+        item (id) {
+            id -> BigInt,
+            name -> Text,
+            note -> Nullable<Text>,
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Queryable, Selectable, Identifiable, Insertable, AsChangeset)]
+    #[diesel(table_name = item, treat_none_as_null = true)]
+    struct Item {
+        id: i64,
+        name: String,
+        note: Option<String>,
+    }
+
+    crate::diesel_repository! {
+        struct ItemRepository<SqliteConnection>: Item, i64 => item,
+            sort: [name];
+    }
+
+    const CREATE_TABLE: &str =
+        "CREATE TABLE item (id BIGINT PRIMARY KEY NOT NULL, name TEXT NOT NULL, note TEXT)";
+
+    fn item(id: i64, name: &str) -> Item {
+        Item {
+            id,
+            name: name.to_string(),
+            note: None,
+        }
+    }
+
+    /// An in-memory database holding an item for each name, with ids counting up from 1
+    fn connection(names: &[&str]) -> SqliteConnection {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute(CREATE_TABLE).unwrap();
+        let items = names.iter().zip(1..).map(|(name, id)| item(id, name));
+        ItemRepository::new(&mut conn).save_all(items).unwrap();
+        conn
+    }
+
+    fn ids(page: &Page<Item, i64>) -> Vec<i64> {
+        page.content().iter().map(|item| item.id).collect()
+    }
+
+    #[test]
+    fn save_inserts_new_entities_and_updates_existing_ones() {
+        let mut conn = connection(&[]);
+        let mut items = ItemRepository::new(&mut conn);
+
+        let mut saved = items
+            .save(Item {
+                note: Some("note".to_string()),
+                ..item(1, "a")
+            })
+            .unwrap();
+        assert_eq!(items.count().unwrap(), 1);
+        assert_eq!(items.find_by_id(1).unwrap().as_ref(), Some(&saved));
+
+        saved.name = "b".to_string();
+        saved.note = None;
+        items.save(saved.clone()).unwrap();
+        assert_eq!(items.count().unwrap(), 1);
+        assert_eq!(items.find_by_id(1).unwrap(), Some(saved));
+        assert!(items.exists_by_id(1).unwrap());
+        assert!(!items.exists_by_id(2).unwrap());
+    }
+
+    #[test]
+    fn deletes_by_ids() {
+        let mut conn = connection(&["a", "b", "c", "d"]);
+        let mut items = ItemRepository::new(&mut conn);
+
+        items.delete_by_ids([1, 3, 5]).unwrap();
+        let left = items.find_all_by_id([1, 2, 3, 4]).unwrap();
+        assert_eq!(left.iter().map(|item| item.id).collect::<Vec<_>>(), [2, 4]);
+        items.delete_by_ids([]).unwrap();
+        assert_eq!(items.count().unwrap(), 2);
+        items.delete_all().unwrap();
+        assert_eq!(items.count().unwrap(), 0);
+    }
+
+    #[test]
+    fn sorts_by_listed_properties_only() {
+        let mut conn = connection(&["b", "c", "a"]);
+        let mut items = ItemRepository::new(&mut conn);
+
+        let sorted = Pageable::first(2).with_sort(Sort::by("name", Direction::Desc));
+        let page = items.find_all(&sorted).unwrap();
+        assert_eq!(ids(&page), [2, 1]);
+        let next = items.find_all(page.next().unwrap()).unwrap();
+        assert_eq!(ids(&next), [3]);
+        assert!(next.is_last());
+
+        let unknown = Pageable::first(2).with_sort(Sort::by("note", Direction::Asc));
+        assert!(matches!(
+            items.find_all(&unknown),
+            Err(RepositoryError::UnknownProperty(property)) if property == "note"
+        ));
+    }
+
+    #[test]
+    fn cursor_pages_end_after_the_last_entity() {
+        let mut conn = connection(&[]);
+        let mut items = ItemRepository::new(&mut conn);
+        let empty = items.find_all(&Pageable::after(0, 2)).unwrap();
+        assert!(empty.content().is_empty());
+        assert!(empty.is_last());
+
+        let mut conn = connection(&["a", "b", "c"]);
+        let mut items = ItemRepository::new(&mut conn);
+        let first = items.find_all(&Pageable::after(0, 2)).unwrap();
+        assert_eq!((ids(&first), first.total()), (vec![1, 2], 3));
+        assert_eq!(first.next(), Some(&Pageable::after(2, 2)));
+        let last = items.find_all(first.next().unwrap()).unwrap();
+        assert_eq!(ids(&last), [3]);
+        assert!(last.is_last());
+
+        // a full last page can't tell that nothing follows, so the page after it is empty
+        let mut conn = connection(&["a", "b"]);
+        let mut items = ItemRepository::new(&mut conn);
+        let full = items.find_all(&Pageable::after(0, 2)).unwrap();
+        let after = items.find_all(full.next().unwrap()).unwrap();
+        assert!(after.content().is_empty());
+        assert!(after.is_last());
+    }
+
+    #[tokio::test]
+    async fn pooled_repositories_run_blocking_repositories() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = dir.path().join("items.db");
+        let pool = Pool::builder()
+            .max_size(1)
+            .build(ConnectionManager::<SqliteConnection>::new(url.to_str().unwrap()))
+            .unwrap();
+        pool.get().unwrap().batch_execute(CREATE_TABLE).unwrap();
 
-    /// Stores the object
-    fn save(&mut self, obj: T);
-    fn save_all<I: IntoIterator<Item = T>>(&mut self, obj: I);
+        let items = PooledRepository::<_, ItemRepository<_>>::new(pool);
+        items
+            .save_all(vec![item(1, "a"), item(2, "b")])
+            .await
+            .unwrap();
+        items.delete_by_ids(vec![1]).await.unwrap();
+        assert_eq!(items.count().await.unwrap(), 1);
+        assert_eq!(items.find_by_id(2).await.unwrap(), Some(item(2, "b")));
+    }
 }
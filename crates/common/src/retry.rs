@@ -0,0 +1,88 @@
+//! Retrying with exponential backoff
+
+use rand::Rng;
+use tracing::warn;
+use std::fmt::Display;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait between attempts, and how many attempts to make
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    /// The wait after the first failure
+    pub initial: Duration,
+    /// The longest wait between two attempts
+    pub max: Duration,
+    /// The number of attempts before giving up, or `None` to never give up
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// The wait before the given retry, starting at 1
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+
+    /// The wait before the given retry, shortened by up to half at random so that clients which
+    /// failed together do not all retry together
+    pub fn jittered_delay(&self, retry: u32) -> Duration {
+        let delay = self.delay(retry);
+        delay - delay.mul_f64(rand::thread_rng().gen_range(0.0..0.5))
+    }
+
+    /// Calls `f` until it succeeds, sleeping the current thread between attempts.
+    ///
+    /// Returns the last error once `max_attempts` is reached.
+    pub fn retry<T, E: Display>(
+        &self,
+        what: &str,
+        mut f: impl FnMut() -> Result<T, E>,
+    ) -> Result<T, E> {
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Ok(ok) => return Ok(ok),
+                Err(e) if self.max_attempts.is_none_or(|max| attempt < max) => {
+                    let delay = self.delay(attempt);
+                    warn!("{what} failed (attempt {attempt}): {e}, retrying in {delay:?}");
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_double_up_to_the_max() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(300),
+            max_attempts: Some(4),
+        };
+        let delays: Vec<_> = (1..=3).map(|retry| backoff.delay(retry)).collect();
+        assert_eq!(delays, [100, 200, 300].map(Duration::from_millis).to_vec());
+
+        for retry in 1..=3 {
+            let jittered = backoff.jittered_delay(retry);
+            assert!(jittered <= backoff.delay(retry));
+            assert!(jittered >= backoff.delay(retry) / 2);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Values that must never be logged, such as tokens, passwords and keys
+//!
+//! A [`Secret`] prints as `[REDACTED]` with both `Debug` and `Display`, so it can be held by
+//! structs deriving `Debug` and passed to `#[instrument]`ed functions. Its value is only read
+//! through [`Secret::expose_secret`], and is zeroed when it is dropped.
+//!
+//! Secrets deserialize like the value they hold, but do not serialize unless a field opts in
+//! with [`serialize_exposed`].
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use zeroize::Zeroize;
+
+/// What a secret prints as
+pub const REDACTED: &str = "[REDACTED]";
+
+/// A value that is redacted when printed and zeroed when dropped
+pub struct Secret<T: Zeroize>(T);
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// The value of the secret, which should not be kept around longer than needed
+    pub fn expose_secret(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zeroize> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: Zeroize + Clone> Clone for Secret<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: Zeroize + Default> Default for Secret<T> {
+    fn default() -> Self {
+        Self(T::default())
+    }
+}
+
+impl<T: Zeroize + PartialEq> PartialEq for Secret<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<T: Zeroize + Eq> Eq for Secret<T> {}
+
+impl<T: Zeroize + Hash> Hash for Secret<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl<T: Zeroize> Debug for Secret<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T: Zeroize> Display for Secret<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<'de, T: Zeroize + Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Secret)
+    }
+}
+
+/// Serializes the value of a secret, for fields that have to be written out.
+///
+/// Use with `#[serde(serialize_with = "common::secret::serialize_exposed")]`.
+pub fn serialize_exposed<T, S>(secret: &Secret<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Zeroize + Serialize,
+    S: Serializer,
+{
+    secret.expose_secret().serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Login {
+        user: String,
+        #[serde(serialize_with = "serialize_exposed")]
+        password: Secret<String>,
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let login: Login =
+            serde_json::from_str(r#"{ "user": "a", "password": "hunter2" }"#).unwrap();
+        assert_eq!(login.password.expose_secret(), "hunter2");
+        let printed = format!("{login:?} {}", login.password);
+        assert!(!printed.contains("hunter2"), "{printed}");
+        assert_eq!(
+            printed,
+            r#"Login { user: "a", password: [REDACTED] } [REDACTED]"#
+        );
+    }
+
+    #[test]
+    fn secrets_serialize_when_exposed() {
+        let login = Login {
+            user: "a".to_string(),
+            password: Secret::from("hunter2".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_string(&login).unwrap(),
+            r#"{"user":"a","password":"hunter2"}"#
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! Graceful shutdown shared by every binary.
+//!
+//! Once `SIGTERM` or `Ctrl-C` is received, a [`Shutdown`]
+//! 1. fails readiness, through the check from [`Shutdown::readiness`]
+//! 2. waits for the drain delay, so load balancers can stop sending requests
+//! 3. stops accepting connections, and gives in-flight requests the grace period to finish
+//! 4. waits for its background tasks, then flushes traces
+//!
+//! The server must be built with [`disable_signals`](actix_web::HttpServer::disable_signals) and
+//! a [`shutdown_timeout`](actix_web::HttpServer::shutdown_timeout) of the grace period.
+
+use crate::config::ShutdownConfig;
+use crate::health::HealthCheck;
+use actix_web::dev::Server;
+use tracing::{info, warn};
+use parking_lot::Mutex;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout_at, Instant};
+
+/// Coordinates the shutdown of a server and its background tasks
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    drain_delay: Duration,
+    grace_period: Duration,
+    draining: watch::Sender<bool>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Shutdown {
+    pub fn new(config: &ShutdownConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                drain_delay: Duration::from_secs(config.drain_delay),
+                grace_period: Duration::from_secs(config.grace_period),
+                draining: watch::channel(false).0,
+                tasks: Mutex::default(),
+            }),
+        }
+    }
+
+    /// How long in-flight requests and background tasks get to finish
+    pub fn grace_period(&self) -> Duration {
+        self.inner.grace_period
+    }
+
+    /// Whether shutting down has begun
+    pub fn is_draining(&self) -> bool {
+        *self.inner.draining.borrow()
+    }
+
+    /// Starts shutting down, failing readiness
+    pub fn begin(&self) {
+        self.inner.draining.send_replace(true);
+    }
+
+    /// Waits until shutting down has begun
+    pub async fn draining(&self) {
+        let mut draining = self.inner.draining.subscribe();
+        // the sender lives as long as self, so this can't fail
+        let _ = draining.wait_for(|draining| *draining).await;
+    }
+
+    /// Runs a background task that is waited on before exiting. The task should finish soon
+    /// after [`draining`](Self::draining) resolves.
+    pub fn spawn<F: Future<Output = ()> + Send + 'static>(&self, task: F) {
+        self.inner.tasks.lock().push(tokio::spawn(task));
+    }
+
+    /// A readiness check that fails once shutting down has begun
+    pub fn readiness(&self) -> ShutdownCheck {
+        ShutdownCheck(self.clone())
+    }
+
+    /// Runs the server until a shutdown signal is received, then shuts everything down
+    pub async fn run(self, server: Server) -> io::Result<()> {
+        let handle = server.handle();
+        let signalled = self.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = shutdown_signal() => {
+                    info!("received shutdown signal, draining");
+                    signalled.begin();
+                }
+                _ = signalled.draining() => {}
+            }
+            sleep(signalled.inner.drain_delay).await;
+            info!(
+                "no longer accepting connections, waiting up to {:?} for in-flight requests",
+                signalled.grace_period()
+            );
+            handle.stop(true).await;
+        });
+
+        let result = server.await;
+        self.finish().await;
+        result
+    }
+
+    /// Waits for background tasks, for at most the grace period, then flushes traces
+    async fn finish(&self) {
+        self.begin();
+        let deadline = Instant::now() + self.grace_period();
+        let tasks = std::mem::take(&mut *self.inner.tasks.lock());
+        for task in tasks {
+            let abort = task.abort_handle();
+            if timeout_at(deadline, task).await.is_err() {
+                warn!("background task did not finish within the grace period");
+                abort.abort();
+            }
+        }
+        info!("shut down");
+        crate::logging::flush();
+    }
+}
+
+/// Fails once shutting down has begun
+#[derive(Debug)]
+pub struct ShutdownCheck(Shutdown);
+
+impl HealthCheck for ShutdownCheck {
+    fn name(&self) -> &str {
+        "shutdown"
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.0.is_draining() {
+            Err("shutting down".to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(e) => {
+                warn!("could not listen for SIGTERM: {e}");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, HttpServer};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[actix_web::test]
+    async fn drains_then_waits_for_background_tasks() {
+        let shutdown = Shutdown::new(&ShutdownConfig {
+            drain_delay: 0,
+            grace_period: 5,
+        });
+        let readiness = shutdown.readiness();
+        assert!(readiness.check().is_ok());
+
+        let finished = Arc::new(AtomicBool::new(false));
+        let task = shutdown.clone();
+        let flag = finished.clone();
+        shutdown.spawn(async move {
+            task.draining().await;
+            flag.store(true, Ordering::SeqCst);
+        });
+
+        let server = HttpServer::new(App::new)
+            .disable_signals()
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap()
+            .run();
+        shutdown.begin();
+        shutdown.clone().run(server).await.unwrap();
+
+        assert!(readiness.check().is_err());
+        assert!(finished.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,470 @@
+//! Request ids and W3C trace context, so the logs of services calling each other can be correlated.
+//!
+//! Wrap the app in [`RequestTracing`]. Every request then
+//! 1. keeps the `X-Request-Id` it was sent with, or gets a new one
+//! 2. joins the trace of its `traceparent` header, or starts a new trace
+//! 3. is handled within a span recording both, with the [`RequestContext`] available through
+//!    [`RequestContext::current`] for outgoing calls
+//! 4. returns both headers in its response, with this service as the parent
+//!
+//! When spans are exported with OpenTelemetry, the request span is the child of the incoming
+//! `traceparent`, and the ids of the exported span are the ones passed on.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::HttpMessage;
+use futures_util::future::LocalBoxFuture;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{SpanContext, TraceContextExt};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use rand::Rng;
+use std::fmt::{Display, Formatter};
+use std::future::{ready, Future, Ready};
+use std::str::FromStr;
+use tracing::field::Empty;
+use tracing::{info_span, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// The header identifying a request across services
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+/// The W3C trace context header
+pub const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+
+/// The longest request id that is accepted instead of generating a new one
+const MAX_REQUEST_ID_LEN: usize = 200;
+
+tokio::task_local! {
+    static CURRENT: RequestContext;
+}
+
+/// The `traceparent` of a request, as defined by the W3C trace context
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParent {
+    trace_id: u128,
+    parent_id: u64,
+    flags: u8,
+}
+
+impl TraceParent {
+    /// Starts a new, sampled trace
+    pub fn new() -> Self {
+        let mut rng = rand::thread_rng();
+        Self {
+            trace_id: rng.gen_range(1..=u128::MAX),
+            parent_id: rng.gen_range(1..=u64::MAX),
+            flags: 1,
+        }
+    }
+
+    /// A span of the same trace, with this one as its parent
+    pub fn child(&self) -> Self {
+        Self {
+            parent_id: rand::thread_rng().gen_range(1..=u64::MAX),
+            ..*self
+        }
+    }
+
+    /// The ids of an OpenTelemetry span, if it has any
+    fn from_span_context(span: &SpanContext) -> Option<Self> {
+        span.is_valid().then(|| Self {
+            trace_id: u128::from_be_bytes(span.trace_id().to_bytes()),
+            parent_id: u64::from_be_bytes(span.span_id().to_bytes()),
+            flags: span.trace_flags().to_u8(),
+        })
+    }
+
+    /// The id shared by every span of the trace, as 32 hex digits
+    pub fn trace_id(&self) -> String {
+        format!("{:032x}", self.trace_id)
+    }
+
+    /// The id of the span this one was sent from, as 16 hex digits
+    pub fn parent_id(&self) -> String {
+        format!("{:016x}", self.parent_id)
+    }
+}
+
+impl Default for TraceParent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display for TraceParent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{:02x}",
+            self.trace_id(),
+            self.parent_id(),
+            self.flags
+        )
+    }
+}
+
+/// A `traceparent` header was not valid
+#[derive(Debug, thiserror::Error)]
+#[error("invalid traceparent {0:?}")]
+pub struct InvalidTraceParent(String);
+
+impl FromStr for TraceParent {
+    type Err = InvalidTraceParent;
+
+    /// Parses a version `00` traceparent, `00-{trace id}-{parent id}-{flags}`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidTraceParent(s.to_string());
+        let mut parts = s.split('-');
+        let (Some(version), Some(trace_id), Some(parent_id), Some(flags), None) = (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) else {
+            return Err(invalid());
+        };
+        if hex(version, 2).ok_or_else(invalid)? != "00" {
+            return Err(invalid());
+        }
+        let trace_id = u128::from_str_radix(hex(trace_id, 32).ok_or_else(invalid)?, 16)
+            .map_err(|_| invalid())?;
+        let parent_id = u64::from_str_radix(hex(parent_id, 16).ok_or_else(invalid)?, 16)
+            .map_err(|_| invalid())?;
+        let flags =
+            u8::from_str_radix(hex(flags, 2).ok_or_else(invalid)?, 16).map_err(|_| invalid())?;
+        if trace_id == 0 || parent_id == 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            trace_id,
+            parent_id,
+            flags,
+        })
+    }
+}
+
+/// Lowercase hex digits of the given length, as required in a traceparent
+fn hex(part: &str, len: usize) -> Option<&str> {
+    (part.len() == len && part.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')))
+        .then_some(part)
+}
+
+/// Identifies a request, and the span of the trace handling it in this service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestContext {
+    request_id: String,
+    trace: TraceParent,
+}
+
+impl RequestContext {
+    /// Continues the context sent with a request, filling in whatever is missing or invalid
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let request_id = headers
+            .get(&REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+            .map_or_else(|| uuid::Uuid::new_v4().to_string(), str::to_string);
+        let trace = headers
+            .get(&TRACEPARENT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<TraceParent>().ok())
+            .map_or_else(TraceParent::new, |parent| parent.child());
+        Self { request_id, trace }
+    }
+
+    /// The context of the request being handled by the current task, if any
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Self::clone).ok()
+    }
+
+    /// Runs a future with this as the [current](Self::current) context
+    pub fn scope<F: Future>(self, f: F) -> impl Future<Output = F::Output> {
+        CURRENT.scope(self, f)
+    }
+
+    /// The id of the request
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// The span of the trace handling the request in this service
+    pub fn trace(&self) -> &TraceParent {
+        &self.trace
+    }
+
+    /// Makes a span the child of the incoming `traceparent`. If the span is exported, this context
+    /// then passes on its ids.
+    fn join(&mut self, span: &Span, headers: &HeaderMap) {
+        span.set_parent(TraceContextPropagator::new().extract(&HeaderExtractor(headers)));
+        if let Some(exported) = TraceParent::from_span_context(span.context().span().span_context())
+        {
+            self.trace = exported;
+        }
+        span.record("trace_id", self.trace.trace_id());
+        span.record("span_id", self.trace.parent_id());
+    }
+
+    /// The headers continuing this context, for outgoing requests and responses
+    pub fn headers(&self) -> [(HeaderName, HeaderValue); 2] {
+        [
+            (
+                REQUEST_ID,
+                HeaderValue::from_str(&self.request_id).expect("request ids are valid headers"),
+            ),
+            (
+                TRACEPARENT,
+                HeaderValue::from_str(&self.trace.to_string())
+                    .expect("traceparents are valid headers"),
+            ),
+        ]
+    }
+}
+
+/// Reads the trace context of a request for OpenTelemetry
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// Middleware giving every request a [`RequestContext`].
+///
+/// It should wrap every other middleware, so their logs are within the request span.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestTracing;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTracing
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestTracingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTracingMiddleware { service }))
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let mut context = RequestContext::from_headers(req.headers());
+        let span = info_span!(
+            "request",
+            request_id = %context.request_id(),
+            trace_id = Empty,
+            span_id = Empty,
+            method = %req.method(),
+            path = %req.path(),
+        );
+        context.join(&span, req.headers());
+        req.extensions_mut().insert(context.clone());
+        let response = {
+            let _entered = span.enter();
+            context.clone().scope(self.service.call(req))
+        };
+
+        Box::pin(
+            async move {
+                let mut response = response.await?;
+                for (name, value) in context.headers() {
+                    response.headers_mut().insert(name, value);
+                }
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use futures_util::future::BoxFuture;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+    use opentelemetry_sdk::trace::TracerProvider;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Keeps the spans it exports
+    #[derive(Debug, Clone, Default)]
+    struct Exported(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for Exported {
+        fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+            self.0.lock().extend(batch);
+            Box::pin(std::future::ready(Ok(())))
+        }
+    }
+
+    async fn echo_context() -> HttpResponse {
+        let context = RequestContext::current().unwrap();
+        HttpResponse::Ok().body(format!("{} {}", context.request_id(), context.trace()))
+    }
+
+    #[actix_web::test]
+    async fn generates_missing_context() {
+        let app = init_service(
+            App::new()
+                .wrap(RequestTracing)
+                .route("/", web::get().to(echo_context)),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/").to_request();
+        let resp = call_service(&app, req).await;
+        let request_id = resp.headers().get(&REQUEST_ID).unwrap().clone();
+        let traceparent = resp.headers().get(&TRACEPARENT).unwrap().clone();
+        let traceparent: TraceParent = traceparent.to_str().unwrap().parse().unwrap();
+
+        let body = read_body(resp).await;
+        assert_eq!(
+            body,
+            format!("{} {}", request_id.to_str().unwrap(), traceparent)
+        );
+    }
+
+    #[actix_web::test]
+    async fn continues_incoming_context() {
+        let app = init_service(
+            App::new()
+                .wrap(RequestTracing)
+                .route("/", web::get().to(echo_context)),
+        )
+        .await;
+
+        let incoming = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID, "abc-123"))
+            .insert_header((TRACEPARENT, incoming))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.headers().get(&REQUEST_ID).unwrap(), "abc-123");
+
+        let traceparent: TraceParent = resp
+            .headers()
+            .get(&TRACEPARENT)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let incoming: TraceParent = incoming.parse().unwrap();
+        assert_eq!(traceparent.trace_id(), incoming.trace_id());
+        assert_ne!(traceparent.parent_id(), incoming.parent_id());
+    }
+
+    #[actix_web::test]
+    async fn exported_spans_join_the_incoming_trace() {
+        let exported = Exported::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exported.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = init_service(
+            App::new()
+                .wrap(RequestTracing)
+                .route("/", web::get().to(echo_context)),
+        )
+        .await;
+        let incoming: TraceParent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+            .parse()
+            .unwrap();
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header((TRACEPARENT, incoming.to_string()))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        let outgoing: TraceParent = resp
+            .headers()
+            .get(&TRACEPARENT)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        drop(resp);
+        provider.force_flush();
+
+        let spans = exported.0.lock();
+        let span = spans.iter().find(|span| span.name == "request").unwrap();
+        let context = &span.span_context;
+        assert_eq!(context.trace_id().to_string(), incoming.trace_id());
+        assert_eq!(span.parent_span_id.to_string(), incoming.parent_id());
+        assert_eq!(context.span_id().to_string(), outgoing.parent_id());
+        assert_eq!(outgoing.trace_id(), incoming.trace_id());
+    }
+
+    #[actix_web::test]
+    async fn errors_keep_the_context() {
+        let app =
+            init_service(App::new().wrap(RequestTracing).route(
+                "/",
+                web::get().to(|| async {
+                    Err::<HttpResponse, _>(actix_web::error::ErrorBadRequest("no"))
+                }),
+            ))
+            .await;
+
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID, "abc-123"))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        assert_eq!(resp.headers().get(&REQUEST_ID).unwrap(), "abc-123");
+    }
+
+    #[test]
+    fn parses_traceparents() {
+        let valid = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        assert_eq!(valid.parse::<TraceParent>().unwrap().to_string(), valid);
+
+        for invalid in [
+            "",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert!(invalid.parse::<TraceParent>().is_err(), "{invalid}");
+        }
+    }
+}
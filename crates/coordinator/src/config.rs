@@ -0,0 +1,27 @@
+//! Configuration of the coordinator
+
+use common::config::{Config, LoggingConfig, ServerConfig, ShutdownConfig};
+use serde::{Deserialize, Serialize};
+
+/// Every setting of the coordinator
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CoordinatorConfig {
+    pub logging: LoggingConfig,
+    pub server: ServerConfig,
+    pub shutdown: ShutdownConfig,
+}
+
+impl Config for CoordinatorConfig {
+    const ENV_PREFIX: &'static str = "COORDINATOR_";
+    const ENV_ALIASES: &'static [(&'static str, &'static str)] = &[
+        ("RUST_LOG", "logging.filter"),
+        ("OTEL_EXPORTER_OTLP_ENDPOINT", "logging.otlp_endpoint"),
+    ];
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = self.logging.validate();
+        problems.extend(self.server.validate());
+        problems
+    }
+}
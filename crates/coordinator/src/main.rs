@@ -1,26 +1,58 @@
-use actix_web::body::BoxBody;
-use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
-use actix_web::http::header::{HeaderValue, CONTENT_TYPE};
 use actix_web::middleware::Logger;
+use actix_web::web::Data;
 use actix_web::{main, App, HttpServer};
 use clap::Parser;
-use common::cli::CommonArgs;
+use common::cli::{CommonArgs, ConfigArgs};
+use common::config::{load, to_redacted_toml};
+use common::health::Health;
+use common::metrics::RequestMetrics;
 use common::logging::init_logging;
+use common::shutdown::Shutdown;
+use common::trace_context::RequestTracing;
 use std::error::Error;
-use tracing::log::LevelFilter;
 
+use crate::config::CoordinatorConfig;
+
+mod config;
 mod home;
 
+#[derive(Parser)]
+struct Args {
+    #[clap(flatten)]
+    config: ConfigArgs,
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
 #[main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let cli = CommonArgs::parse();
+    let cli = Args::parse();
+    let config: CoordinatorConfig = load(&cli.config, &cli.common)?;
+    if cli.config.print_config {
+        print!("{}", to_redacted_toml(&config)?);
+        return Ok(());
+    }
+
+    init_logging(&config.logging, env!("CARGO_PKG_NAME"))?;
 
-    init_logging(&cli.logging);
+    let shutdown = Shutdown::new(&config.shutdown);
+    let health = Data::new(Health::new().with_check(shutdown.readiness()));
 
-    HttpServer::new(|| App::new().wrap(Logger::default()).service(home::home))
-        .bind((cli.server.ip, cli.server.port))?
-        .run()
-        .await?;
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(RequestMetrics)
+            .wrap(Logger::default())
+            .wrap(RequestTracing)
+            .app_data(health.clone())
+            .configure(common::health::configure)
+            .configure(common::metrics::configure)
+            .service(home::home)
+    })
+        .disable_signals()
+        .shutdown_timeout(shutdown.grace_period().as_secs())
+        .bind((config.server.ip, config.server.port))?
+        .run();
+    shutdown.run(server).await?;
 
     Ok(())
 }
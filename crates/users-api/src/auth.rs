@@ -1,18 +1,40 @@
 //! Defines the auth service
 
 use crate::bearer::BearerToken;
+use crate::claims::Claims;
 use crate::error::AuthError;
 use crate::ExpirationTime;
-use argon2::password_hash::{Salt, SaltString};
+use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use std::time::Instant;
 use thiserror::Error;
 
-/// Used for confirming if a bearer token is valid
+pub(crate) mod local;
+mod remote;
+
+pub use local::LocalAuthService;
+pub use remote::RemoteAuthService;
+
+/// Used for confirming if a bearer token is valid.
+///
+/// [`LocalAuthService`] verifies tokens in-process, and [`RemoteAuthService`] asks the users
+/// service.
 pub trait AuthService {
+    /// Validates a bearer token, returning its claims if it's valid. Otherwise, an auth error is
+    /// returned.
+    fn claims(&self, token: &BearerToken) -> Result<Claims, AuthError>;
+
     /// Validates a bearer token, returning whether it's valid or not. If valid, the expiration time is
     /// returned. Otherwise, an auth error is returned.
-    fn validate_token(&self, token: &BearerToken) -> Result<ExpirationTime, AuthError>;
+    fn validate_token(&self, token: &BearerToken) -> Result<ExpirationTime, AuthError> {
+        self.claims(token).map(|claims| claims.expires)
+    }
+
+    /// Whether validating waits on another service. Such services are only called from the
+    /// blocking thread pool, by the [`Authentication`](crate::authentication::Authentication)
+    /// middleware, and never from the async workers of actix.
+    fn blocks(&self) -> bool {
+        false
+    }
 }
 
 /// The password hash factory
@@ -38,7 +60,7 @@ impl PasswordAuth {
     /// Hashes a password
     pub fn hash_password(&self, password: &[u8]) -> Result<String, PasswordError> {
         let argon = Argon2::default();
-        let ref salt = self.salt.as_ref().cloned().unwrap_or_else(|| SaltString::generate(rand::thread_rng()));
+        let salt = &self.salt.as_ref().cloned().unwrap_or_else(|| SaltString::generate(rand::thread_rng()));
         let hashed = argon
             .hash_password(password, salt)
             .map_err(|e| PasswordError::InvalidPasswordHash(e.to_string()))?;
@@ -47,11 +69,17 @@ impl PasswordAuth {
 
     /// Verifies a password against a hash
     pub fn verify_password(&self, password: &[u8], hash: &str) -> Result<(), PasswordError> {
-        let ref parsed = PasswordHash::new(hash)
+        let parsed = &PasswordHash::new(hash)
             .map_err(|e| PasswordError::InvalidPasswordHash(e.to_string()))?;
         Argon2::default()
             .verify_password(password, parsed)
-            .map_err(|e| PasswordError::IncorrectPassword)
+            .map_err(|_| PasswordError::IncorrectPassword)
+    }
+}
+
+impl Default for PasswordAuth {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
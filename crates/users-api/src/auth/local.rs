@@ -0,0 +1,115 @@
+use super::AuthService;
+use crate::bearer::BearerToken;
+use crate::claims::Claims;
+use crate::error::AuthError;
+use crate::scope::Scope;
+use crate::ExpirationTime;
+use hmac::digest::KeyInit;
+use hmac::Hmac;
+use jwt::VerifyWithKey;
+use serde::Deserialize;
+use sha2::Sha384;
+use std::time::SystemTime;
+
+/// Validates tokens in-process, by verifying their signature with the secret the users service
+/// signs them with.
+///
+/// Revocation is only known to the users service, so tokens of users who logged out stay valid
+/// until they expire.
+#[derive(Debug, Clone)]
+pub struct LocalAuthService {
+    hmac: Hmac<Sha384>,
+}
+
+impl LocalAuthService {
+    /// Creates a validator sharing the signing secret of the users service
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            hmac: Hmac::new_from_slice(secret).expect("hmac accepts keys of any length"),
+        }
+    }
+}
+
+/// The claims of a token as signed by the users service
+#[derive(Debug, Deserialize)]
+struct TokenClaims {
+    subject: String,
+    scope: Scope,
+    expiration_time: ExpirationTime,
+    #[serde(default)]
+    user_id: Option<i64>,
+    #[serde(default)]
+    username: Option<String>,
+}
+
+impl AuthService for LocalAuthService {
+    fn claims(&self, token: &BearerToken) -> Result<Claims, AuthError> {
+        let claims: TokenClaims =
+            String::from_utf8_lossy(token.expose_secret()).verify_with_key(&self.hmac)?;
+        if claims.expiration_time < ExpirationTime::from(SystemTime::now()) {
+            return Err(AuthError::TokenExpired(claims.expiration_time));
+        }
+        Ok(Claims {
+            subject: claims.subject,
+            user_id: claims.user_id,
+            username: claims.username,
+            scopes: vec![claims.scope],
+            expires: claims.expiration_time,
+        })
+    }
+}
+
+/// Signs claims as the users service would
+#[cfg(any(test, feature = "test-util"))]
+pub(crate) fn sign(secret: &[u8], claims: &serde_json::Value) -> BearerToken {
+    use jwt::SignWithKey;
+
+    let hmac: Hmac<Sha384> = Hmac::new_from_slice(secret).unwrap();
+    BearerToken::from(claims.sign_with_key(&hmac).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use serde_json::json;
+
+    fn token(secret: &[u8], expiration_time: ExpirationTime) -> BearerToken {
+        let claims = json!({
+            "subject": "a@example.com",
+            "scope": "user",
+            "expiration_time": expiration_time,
+            "user_id": 1,
+            "username": "a",
+        });
+        sign(secret, &claims)
+    }
+
+    #[test]
+    fn validates_signature_and_expiration() {
+        let auth = LocalAuthService::new(b"secret");
+        let expires = ExpirationTime::from(SystemTime::now()) + Duration::hours(1);
+        assert_eq!(
+            auth.claims(&token(b"secret", expires)).unwrap(),
+            Claims {
+                subject: "a@example.com".to_string(),
+                user_id: Some(1),
+                username: Some("a".to_string()),
+                scopes: vec![Scope::User],
+                expires,
+            }
+        );
+
+        let error = auth.validate_token(&token(b"other", expires)).unwrap_err();
+        assert!(matches!(error, AuthError::JwtError(_)), "{error:?}");
+
+        let expired = ExpirationTime::from(SystemTime::now()) - Duration::hours(1);
+        let error = auth.validate_token(&token(b"secret", expired)).unwrap_err();
+        assert!(matches!(error, AuthError::TokenExpired(_)), "{error:?}");
+
+        let error = auth
+            .validate_token(&BearerToken::from("not a token"))
+            .unwrap_err();
+        assert_eq!(error.code(), crate::error::ErrorCode::InvalidToken);
+    }
+}
@@ -0,0 +1,203 @@
+use super::AuthService;
+use crate::bearer::BearerToken;
+use crate::claims::Claims;
+use crate::client::{ClientBuildError, ClientBuilder, ClientError};
+use crate::error::AuthError;
+use std::sync::mpsc;
+use std::thread;
+use tokio::runtime;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+/// A token to validate, and where to send the result
+type Validation = (BearerToken, mpsc::Sender<Result<Claims, ClientError>>);
+
+/// Validates tokens by asking the users service, so revoked tokens are rejected.
+///
+/// [`AuthService`] is synchronous, so each validation [blocks](AuthService::blocks) until the
+/// service answers. Put the app behind the
+/// [`Authentication`](crate::authentication::Authentication) middleware, which validates on the
+/// blocking thread pool, and guard its routes with
+/// [`AuthorizationGuard`](crate::guard::AuthorizationGuard) or take its extractors.
+///
+/// Validations are made by an async client on a worker thread with a runtime of its own, which is
+/// shared by the clones of the service and stops once they are all dropped.
+#[derive(Debug, Clone)]
+pub struct RemoteAuthService {
+    worker: UnboundedSender<Validation>,
+}
+
+impl RemoteAuthService {
+    /// Creates a validator and starts its worker, which can be done from within a runtime
+    pub fn new(client: ClientBuilder) -> Result<Self, ClientBuildError> {
+        let client = client.build()?;
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(ClientBuildError::Worker)?;
+        let (worker, mut validations) = unbounded_channel::<Validation>();
+        thread::Builder::new()
+            .name("remote-auth".to_string())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    while let Some((token, reply)) = validations.recv().await {
+                        let client = client.clone();
+                        tokio::spawn(async move {
+                            // the caller may have given up waiting
+                            let _ = reply.send(client.claims(&token).await);
+                        });
+                    }
+                })
+            })
+            .map_err(ClientBuildError::Worker)?;
+        Ok(Self { worker })
+    }
+}
+
+impl AuthService for RemoteAuthService {
+    fn claims(&self, token: &BearerToken) -> Result<Claims, AuthError> {
+        let stopped = || AuthError::Unavailable("the validation worker has stopped".to_string());
+        let (reply, result) = mpsc::channel();
+        self.worker
+            .send((token.clone(), reply))
+            .map_err(|_| stopped())?;
+        let result = result.recv().map_err(|_| stopped())?;
+        result.map_err(|error| match error {
+            ClientError::Auth(body) => AuthError::Rejected(body),
+            error => AuthError::Unavailable(error.to_string()),
+        })
+    }
+
+    fn blocks(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authentication::{Authenticated, Authentication};
+    use crate::error::{ErrorBody, ErrorCode};
+    use crate::guard::AuthorizationGuard;
+    use crate::token_cache::TokenCache;
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use serde_json::json;
+    use std::sync::{mpsc, Arc};
+
+    /// Accepts `Bearer valid`, like the claims endpoint of the users service
+    async fn validate(req: HttpRequest) -> HttpResponse {
+        match req.headers().get(AUTHORIZATION) {
+            Some(value) if value == "Bearer valid" => HttpResponse::Ok().json(json!({
+                "subject": "a@example.com",
+                "user_id": 1,
+                "username": "a",
+                "scopes": ["user"],
+                "expires": "2100-01-01T00:00:00Z",
+            })),
+            _ => HttpResponse::Unauthorized()
+                .json(ErrorBody::new(ErrorCode::TokenRevoked, "revoked")),
+        }
+    }
+
+    /// Runs the users service stand-in on its own thread
+    fn serve() -> String {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                let server =
+                    HttpServer::new(|| App::new().route("/claims", web::get().to(validate)))
+                        .workers(1)
+                        .bind(("127.0.0.1", 0))
+                        .unwrap();
+                sender
+                    .send(format!("http://{}", server.addrs()[0]))
+                    .unwrap();
+                server.run().await
+            })
+        });
+        receiver.recv().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn authenticates_requests() {
+        let auth = RemoteAuthService::new(ClientBuilder::new(serve())).unwrap();
+        let cache = Arc::new(TokenCache::new(10));
+        let app = init_service(
+            App::new()
+                .wrap(Authentication::new(cache.clone(), auth.clone()))
+                .route(
+                    "/",
+                    web::get().to(|_: Authenticated| async { HttpResponse::Ok().finish() }),
+                )
+                .route(
+                    "/guarded",
+                    web::get()
+                        .guard(AuthorizationGuard::new(cache, auth.clone()))
+                        .to(HttpResponse::Ok),
+                ),
+        )
+        .await;
+        let request = |uri, bearer| {
+            TestRequest::get()
+                .uri(uri)
+                .insert_header((AUTHORIZATION, bearer))
+                .to_request()
+        };
+
+        let resp = call_service(&app, request("/", "Bearer valid")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = call_service(&app, request("/", "Bearer revoked")).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = call_service(&app, request("/guarded", "Bearer valid")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = call_service(&app, request("/guarded", "Bearer revoked")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let claims = auth.claims(&BearerToken::from("valid")).unwrap();
+        assert_eq!(claims.username.as_deref(), Some("a"));
+        let error = auth
+            .validate_token(&BearerToken::from("revoked"))
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::TokenRevoked);
+    }
+
+    #[actix_web::test]
+    async fn guards_do_not_validate_without_the_middleware() {
+        let auth = RemoteAuthService::new(ClientBuilder::new(serve())).unwrap();
+        let app = init_service(
+            App::new().route(
+                "/",
+                web::get()
+                    .guard(AuthorizationGuard::new(Arc::new(TokenCache::new(10)), auth))
+                    .to(HttpResponse::Ok),
+            ),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header((AUTHORIZATION, "Bearer valid"))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn unreachable_services_are_unavailable() {
+        let auth = RemoteAuthService::new(
+            ClientBuilder::new("http://127.0.0.1:1".to_string()).retries(common::retry::Backoff {
+                max_attempts: Some(1),
+                ..Default::default()
+            }),
+        )
+        .unwrap();
+        let error = auth
+            .validate_token(&BearerToken::from("valid"))
+            .unwrap_err();
+        assert!(matches!(error, AuthError::Unavailable(_)), "{error:?}");
+    }
+}
@@ -0,0 +1,413 @@
+//! Tells handlers who sent a request.
+//!
+//! Wrap the app, or a scope, in [`Authentication`] and take
+//! - [`Authenticated`] to require a valid bearer token
+//! - [`Authenticated<UserScope>`] to also require it to grant the user scope
+//! - [`MaybeAuthenticated`] to also accept requests without a bearer token
+//!
+//! Requests that are refused get a `401` or `403` with a `WWW-Authenticate` header.
+
+use crate::auth::AuthService;
+use crate::claims::Claims;
+use crate::error::{AuthError, ErrorBody, ErrorCode};
+use crate::header::Authorization;
+use crate::scope::Scope;
+use crate::token_cache::TokenCache;
+use actix_web::body::MessageBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::WWW_AUTHENTICATE;
+use actix_web::http::StatusCode;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// The realm of the `WWW-Authenticate` challenges
+const REALM: &str = "users";
+
+/// What the middleware found out about the sender of a request
+#[derive(Debug, Clone)]
+pub(crate) enum Principal {
+    Anonymous,
+    Valid(Claims),
+    Rejected(ErrorBody),
+}
+
+/// Middleware validating the bearer token of every request, for the extractors of this module.
+///
+/// It refuses nothing itself. Validations are cached, and made on the blocking thread pool when
+/// they are not.
+pub struct Authentication<A> {
+    cache: Arc<TokenCache>,
+    auth: Arc<A>,
+}
+
+impl<A> Authentication<A> {
+    pub fn new(cache: Arc<TokenCache>, auth: A) -> Self {
+        Self {
+            cache,
+            auth: Arc::new(auth),
+        }
+    }
+}
+
+impl<A> Clone for Authentication<A> {
+    fn clone(&self) -> Self {
+        Self {
+            cache: self.cache.clone(),
+            auth: self.auth.clone(),
+        }
+    }
+}
+
+impl<S, B, A> Transform<S, ServiceRequest> for Authentication<A>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+    A: AuthService + Send + Sync + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = AuthenticationMiddleware<S, A>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthenticationMiddleware {
+            service: Rc::new(service),
+            authentication: self.clone(),
+        }))
+    }
+}
+
+pub struct AuthenticationMiddleware<S, A> {
+    service: Rc<S>,
+    authentication: Authentication<A>,
+}
+
+impl<S, B, A> Service<ServiceRequest> for AuthenticationMiddleware<S, A>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+    A: AuthService + Send + Sync + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let Authentication { cache, auth } = self.authentication.clone();
+        // other schemes are not meant for this middleware, but a malformed header is refused
+        let bearer = Authorization::find(req.request())
+            .map(|auth| auth.and_then(Authorization::into_bearer))
+            .map_err(|e| ErrorBody::new(ErrorCode::InvalidToken, e));
+
+        Box::pin(async move {
+            let principal = match bearer {
+                Err(body) => Principal::Rejected(body),
+                Ok(None) => Principal::Anonymous,
+                Ok(Some(bearer)) => {
+                    let result = match cache.cached(&bearer) {
+                        Some(result) => result,
+                        None => web::block(move || cache.validate(&bearer, |b| auth.claims(b)))
+                            .await
+                            .unwrap_or_else(|e| Err(AuthError::Unavailable(e.to_string()))),
+                    };
+                    match result {
+                        Ok(claims) => Principal::Valid(claims),
+                        Err(error) => Principal::Rejected(ErrorBody::new(error.code(), error)),
+                    }
+                }
+            };
+            req.extensions_mut().insert(principal);
+            service.call(req).await
+        })
+    }
+}
+
+/// A request was refused by an extractor of this module
+#[derive(Debug, thiserror::Error)]
+pub enum AuthenticationError {
+    #[error("a bearer token is required")]
+    Missing,
+    #[error("{}", .0.message)]
+    Rejected(ErrorBody),
+    #[error("the token does not grant the {0} scope")]
+    InsufficientScope(Scope),
+    #[error("requests are not authenticated, the app is missing the Authentication middleware")]
+    NotConfigured,
+}
+
+impl AuthenticationError {
+    /// The `WWW-Authenticate` challenge of the error, as defined by RFC 6750
+    fn challenge(&self) -> Option<String> {
+        match self {
+            AuthenticationError::Missing => Some(format!("Bearer realm=\"{REALM}\"")),
+            AuthenticationError::Rejected(body) if body.code != ErrorCode::Internal => {
+                Some(format!("Bearer realm=\"{REALM}\", error=\"invalid_token\""))
+            }
+            AuthenticationError::InsufficientScope(scope) => Some(format!(
+                "Bearer realm=\"{REALM}\", error=\"insufficient_scope\", scope=\"{scope}\""
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl ResponseError for AuthenticationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthenticationError::Missing => StatusCode::UNAUTHORIZED,
+            AuthenticationError::Rejected(body) if body.code == ErrorCode::Internal => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            AuthenticationError::Rejected(_) => StatusCode::UNAUTHORIZED,
+            AuthenticationError::InsufficientScope(_) => StatusCode::FORBIDDEN,
+            AuthenticationError::NotConfigured => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let body = match self {
+            AuthenticationError::Rejected(body) => body.clone(),
+            AuthenticationError::Missing => ErrorBody::new(ErrorCode::InvalidToken, self),
+            AuthenticationError::InsufficientScope(_) => {
+                ErrorBody::new(ErrorCode::MissingScope, self)
+            }
+            AuthenticationError::NotConfigured => ErrorBody::new(ErrorCode::Internal, self),
+        };
+        let mut response = HttpResponse::build(self.status_code());
+        if let Some(challenge) = self.challenge() {
+            response.insert_header((WWW_AUTHENTICATE, challenge));
+        }
+        response.json(body)
+    }
+}
+
+/// The scope an [`Authenticated`] requires
+pub trait RequiredScope {
+    const SCOPE: Option<Scope>;
+}
+
+/// Accepts tokens of any scope
+#[derive(Debug)]
+pub struct AnyScope;
+
+impl RequiredScope for AnyScope {
+    const SCOPE: Option<Scope> = None;
+}
+
+/// Only accepts tokens granting the user scope, refusing guests
+#[derive(Debug)]
+pub struct UserScope;
+
+impl RequiredScope for UserScope {
+    const SCOPE: Option<Scope> = Some(Scope::User);
+}
+
+/// The claims of a request with a valid bearer token granting the scope `S`
+#[derive(Debug)]
+pub struct Authenticated<S: RequiredScope = AnyScope> {
+    claims: Claims,
+    _scope: PhantomData<S>,
+}
+
+impl<S: RequiredScope> Authenticated<S> {
+    pub fn into_claims(self) -> Claims {
+        self.claims
+    }
+}
+
+impl<S: RequiredScope> Deref for Authenticated<S> {
+    type Target = Claims;
+
+    fn deref(&self) -> &Self::Target {
+        &self.claims
+    }
+}
+
+impl<S: RequiredScope> FromRequest for Authenticated<S> {
+    type Error = AuthenticationError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let claims = match principal(req) {
+            Err(error) => return ready(Err(error)),
+            Ok(None) => return ready(Err(AuthenticationError::Missing)),
+            Ok(Some(claims)) => claims,
+        };
+        if let Some(scope) = S::SCOPE.filter(|scope| !claims.has_scope(*scope)) {
+            return ready(Err(AuthenticationError::InsufficientScope(scope)));
+        }
+        ready(Ok(Self {
+            claims,
+            _scope: PhantomData,
+        }))
+    }
+}
+
+/// The claims of a request with a valid bearer token, or `None` for a request without one.
+///
+/// Requests with an invalid bearer token are still refused.
+#[derive(Debug)]
+pub struct MaybeAuthenticated(pub Option<Claims>);
+
+impl FromRequest for MaybeAuthenticated {
+    type Error = AuthenticationError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(principal(req).map(MaybeAuthenticated))
+    }
+}
+
+/// The claims found by the middleware, if the request had a bearer token
+fn principal(req: &HttpRequest) -> Result<Option<Claims>, AuthenticationError> {
+    match req.extensions().get::<Principal>() {
+        None => Err(AuthenticationError::NotConfigured),
+        Some(Principal::Anonymous) => Ok(None),
+        Some(Principal::Valid(claims)) => Ok(Some(claims.clone())),
+        Some(Principal::Rejected(body)) => Err(AuthenticationError::Rejected(body.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{local, LocalAuthService};
+    use crate::bearer::BearerToken;
+    use crate::ExpirationTime;
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+    use serde_json::json;
+    use std::time::SystemTime;
+
+    fn token(scope: &str, expires_in: chrono::Duration) -> BearerToken {
+        let claims = json!({
+            "subject": "a@example.com",
+            "scope": scope,
+            "expiration_time": ExpirationTime::from(SystemTime::now()) + expires_in,
+            "user_id": 7,
+            "username": "a",
+        });
+        local::sign(b"secret", &claims)
+    }
+
+    async fn any(auth: Authenticated) -> String {
+        format!("{} {:?}", auth.subject, auth.scopes)
+    }
+
+    async fn user(auth: Authenticated<UserScope>) -> String {
+        format!(
+            "{} {}",
+            auth.user_id.unwrap(),
+            auth.username.as_deref().unwrap()
+        )
+    }
+
+    async fn maybe(auth: MaybeAuthenticated) -> String {
+        auth.0
+            .map_or("anonymous".to_string(), |claims| claims.subject)
+    }
+
+    fn routes(cfg: &mut web::ServiceConfig) {
+        cfg.route("/any", web::get().to(any))
+            .route("/user", web::get().to(user))
+            .route("/maybe", web::get().to(maybe));
+    }
+
+    fn get(path: &str, bearer: Option<&BearerToken>) -> TestRequest {
+        let req = TestRequest::get().uri(path);
+        match bearer {
+            Some(bearer) => req.insert_header(Authorization::Bearer(bearer.clone())),
+            None => req,
+        }
+    }
+
+    #[actix_web::test]
+    async fn extracts_the_principal() {
+        let authentication = Authentication::new(
+            Arc::new(TokenCache::new(10)),
+            LocalAuthService::new(b"secret"),
+        );
+        let app = init_service(App::new().wrap(authentication).configure(routes)).await;
+        let user_token = token("user", chrono::Duration::hours(1));
+        let guest_token = token("guest", chrono::Duration::hours(1));
+
+        let resp = call_service(&app, get("/any", Some(&guest_token)).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(read_body(resp).await, "a@example.com [Guest]");
+        let resp = call_service(&app, get("/user", Some(&user_token)).to_request()).await;
+        assert_eq!(read_body(resp).await, "7 a");
+        let resp = call_service(&app, get("/maybe", Some(&user_token)).to_request()).await;
+        assert_eq!(read_body(resp).await, "a@example.com");
+        let resp = call_service(&app, get("/maybe", None).to_request()).await;
+        assert_eq!(read_body(resp).await, "anonymous");
+        let basic = get("/maybe", None).insert_header((AUTHORIZATION, "Basic dXNlcjpwYXNz"));
+        let resp = call_service(&app, basic.to_request()).await;
+        assert_eq!(read_body(resp).await, "anonymous");
+    }
+
+    #[actix_web::test]
+    async fn refuses_with_a_challenge() {
+        let authentication = Authentication::new(
+            Arc::new(TokenCache::new(10)),
+            LocalAuthService::new(b"secret"),
+        );
+        let app = init_service(App::new().wrap(authentication).configure(routes)).await;
+        let challenge = |resp: &ServiceResponse| {
+            resp.headers()
+                .get(WWW_AUTHENTICATE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let resp = call_service(&app, get("/any", None).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(challenge(&resp), "Bearer realm=\"users\"");
+
+        let expired = token("user", chrono::Duration::hours(-1));
+        for path in ["/any", "/maybe"] {
+            let resp = call_service(&app, get(path, Some(&expired)).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                challenge(&resp),
+                "Bearer realm=\"users\", error=\"invalid_token\""
+            );
+            let body: ErrorBody = serde_json::from_slice(&read_body(resp).await).unwrap();
+            assert_eq!(body.code, ErrorCode::TokenExpired);
+        }
+
+        let malformed = get("/maybe", None).insert_header((AUTHORIZATION, "Bearer a b"));
+        let resp = call_service(&app, malformed.to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            challenge(&resp),
+            "Bearer realm=\"users\", error=\"invalid_token\""
+        );
+
+        let guest_token = token("guest", chrono::Duration::hours(1));
+        let resp = call_service(&app, get("/user", Some(&guest_token)).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            challenge(&resp),
+            "Bearer realm=\"users\", error=\"insufficient_scope\", scope=\"user\""
+        );
+    }
+
+    #[actix_web::test]
+    async fn requires_the_middleware() {
+        let app = init_service(App::new().configure(routes)).await;
+        let resp = call_service(&app, get("/maybe", None).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,28 @@
+//! Who a bearer token was issued to
+
+use crate::scope::Scope;
+use crate::ExpirationTime;
+use serde::{Deserialize, Serialize};
+
+/// The principal a bearer token was issued to, and what it may do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    /// The email of a user, or the id of a guest
+    pub subject: String,
+    /// The id of the user, missing for guests and tokens issued before ids were included
+    pub user_id: Option<i64>,
+    /// The username of the user, missing for guests and tokens issued before usernames were
+    /// included
+    pub username: Option<String>,
+    /// What the token grants
+    pub scopes: Vec<Scope>,
+    /// When the token expires
+    pub expires: ExpirationTime,
+}
+
+impl Claims {
+    /// Whether the token grants the given scope
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
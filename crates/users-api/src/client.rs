@@ -1,97 +1,739 @@
 //! An auth service client
+//!
+//! [`Client`] is async. With the `blocking` feature, [`blocking::Client`] has the same operations
+//! for synchronous callers. Both build their requests as a [`Call`], and read the responses as a
+//! [`Reply`], so they only differ in how these are sent.
 
 use crate::bearer::BearerToken;
+use crate::claims::Claims;
+use crate::error::{ErrorBody, ErrorCode};
 use crate::header::Authorization;
 use crate::user_service::{AuthenticatedUser, UserService};
-use crate::User;
-use actix_web::http::header::Header;
+use crate::{ExpirationTime, User};
+use actix_web::http::header::TryIntoHeaderValue;
 use async_trait::async_trait;
-use common::utils::encode_base64;
+use common::config::TlsConfig;
+use common::retry::Backoff;
+use common::secret::Secret;
+use common::trace_context::RequestContext;
 use email_address::EmailAddress;
-use reqwest::header::AUTHORIZATION;
-use reqwest::Url;
-use std::error::Error;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::tls::{Certificate, Identity};
+use reqwest::{Method, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
+use tracing::warn;
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+
+/// Calling the users service failed
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    /// The service could not be reached, or its response could not be read
+    #[error("could not reach the users service: {0}")]
+    Transport(#[from] reqwest::Error),
+    /// The credentials or bearer token were rejected
+    #[error("{}", .0.message)]
+    Auth(ErrorBody),
+    /// The values sent were not accepted
+    #[error("{}", .0.message)]
+    Validation(ErrorBody),
+    /// The service failed to handle the request
+    #[error("the users service responded with {status}: {}", .body.message)]
+    Server { status: StatusCode, body: ErrorBody },
+    /// The response was not one the users service sends
+    #[error("invalid response from the users service: {0}")]
+    InvalidResponse(String),
+    /// The host of the client is not a valid url
+    #[error("invalid users service url {0:?}")]
+    InvalidUrl(String),
+    /// The request could not be built, so it was not sent
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+}
+
+impl ClientError {
+    /// The code sent by the service, if it responded with an error
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            ClientError::Auth(body)
+            | ClientError::Validation(body)
+            | ClientError::Server { body, .. } => Some(body.code),
+            _ => None,
+        }
+    }
+
+    /// Sorts an error response by its status
+    fn from_response(status: StatusCode, body: ErrorBody) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ClientError::Auth(body),
+            StatusCode::BAD_REQUEST
+            | StatusCode::NOT_ACCEPTABLE
+            | StatusCode::CONFLICT
+            | StatusCode::UNPROCESSABLE_ENTITY => ClientError::Validation(body),
+            status => ClientError::Server { status, body },
+        }
+    }
+}
+
+/// A [`Client`] could not be built
+#[derive(Debug, thiserror::Error)]
+pub enum ClientBuildError {
+    #[error("could not read {path:?}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid tls settings: {0}")]
+    Tls(#[from] reqwest::Error),
+    #[error("could not start the worker of the client: {0}")]
+    Worker(std::io::Error),
+}
+
+/// Configures a [`Client`], or with the `blocking` feature a [`blocking::Client`].
+///
+/// By default connecting times out after 5 seconds, requests after 30 seconds, and idempotent
+/// requests are attempted up to 3 times.
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    host: String,
+    connect_timeout: Duration,
+    timeout: Duration,
+    retries: Backoff,
+    root_ca: Option<PathBuf>,
+    identity: Option<(PathBuf, PathBuf)>,
+}
+
+impl ClientBuilder {
+    pub fn new(host: String) -> Self {
+        Self {
+            host,
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(30),
+            retries: Backoff {
+                initial: Duration::from_millis(100),
+                max: Duration::from_secs(2),
+                max_attempts: Some(3),
+            },
+            root_ca: None,
+            identity: None,
+        }
+    }
+
+    /// How long connecting to the service may take
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// How long a request may take, from connecting until its response has been read
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How idempotent requests are retried when the service can not be reached, times out, or
+    /// responds with 502, 503 or 504. The waits between attempts are jittered.
+    pub fn retries(mut self, retries: Backoff) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Trusts the authority in a PEM file, along with the system's
+    pub fn root_ca(mut self, path: impl Into<PathBuf>) -> Self {
+        self.root_ca = Some(path.into());
+        self
+    }
+
+    /// Presents a certificate to services requiring one, from a PEM certificate and a PEM PKCS#8
+    /// private key
+    pub fn client_cert(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.identity = Some((cert.into(), key.into()));
+        self
+    }
+
+    /// Trusts the authority and presents the certificate of a service's tls settings
+    pub fn tls(mut self, tls: &TlsConfig) -> Self {
+        if let Some(capath) = &tls.capath {
+            self = self.root_ca(capath);
+        }
+        if let (Some(cert), Some(key)) = (&tls.cert, &tls.cert_key) {
+            self = self.client_cert(cert, key);
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<Client, ClientBuildError> {
+        let (root_ca, identity) = self.read_tls()?;
+        let mut client = reqwest::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.timeout);
+        if let Some(root_ca) = root_ca {
+            client = client.add_root_certificate(root_ca);
+        }
+        if let Some(identity) = identity {
+            client = client.identity(identity);
+        }
+
+        Ok(Client {
+            host: self.host,
+            client: client.build()?,
+            retries: self.retries,
+        })
+    }
+
+    /// Reads the root authority and client certificate
+    fn read_tls(&self) -> Result<(Option<Certificate>, Option<Identity>), ClientBuildError> {
+        let root_ca = match &self.root_ca {
+            Some(path) => Some(Certificate::from_pem(&read(path)?)?),
+            None => None,
+        };
+        let identity = match &self.identity {
+            Some((cert, key)) => Some(Identity::from_pkcs8_pem(&read(cert)?, &read(key)?)?),
+            None => None,
+        };
+        Ok((root_ca, identity))
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, ClientBuildError> {
+    std::fs::read(path).map_err(|source| ClientBuildError::Read {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// A request to the users service, which either client can send as many times as it is attempted.
+///
+/// Not `Debug`, as its body can hold a password.
+#[derive(Clone)]
+struct Call {
+    method: Method,
+    path: &'static str,
+    query: Vec<(&'static str, String)>,
+    headers: HeaderMap,
+    body: Option<Vec<u8>>,
+    /// Why the call can not be sent, such as credentials that can not be put in a header
+    invalid: Option<String>,
+}
+
+impl Call {
+    /// Starts a request, forwarding the request id and trace context of the request being handled
+    fn new(method: Method, path: &'static str) -> Self {
+        let mut headers = HeaderMap::new();
+        if let Some(context) = RequestContext::current() {
+            headers.extend(context.headers());
+        }
+        Self {
+            method,
+            path,
+            query: vec![],
+            headers,
+            body: None,
+            invalid: None,
+        }
+    }
+
+    fn sign_up(email: &str, username: &str, password: &str) -> Self {
+        Call::new(Method::POST, "/user/create").json(&NewUser {
+            email,
+            username,
+            password,
+        })
+    }
+
+    fn log_in(user: &str, pass: &[u8]) -> Self {
+        let call = Call::new(Method::POST, "/user/login");
+        match std::str::from_utf8(pass) {
+            Ok(pass) => call.authorization(Authorization::Basic {
+                user: user.to_string(),
+                pass: Secret::new(pass.to_string()),
+            }),
+            Err(_) => call.invalid("the password is not valid UTF-8"),
+        }
+    }
+
+    fn validate_token(bearer: &BearerToken) -> Self {
+        Call::new(Method::GET, "/").bearer(bearer)
+    }
+
+    fn claims(bearer: &BearerToken) -> Self {
+        Call::new(Method::GET, "/claims").bearer(bearer)
+    }
+
+    fn profile(bearer: &BearerToken) -> Self {
+        Call::new(Method::GET, "/user/me").bearer(bearer)
+    }
+
+    fn log_out(bearer: &BearerToken) -> Self {
+        Call::new(Method::POST, "/user/logout").bearer(bearer)
+    }
+
+    fn refresh(bearer: &BearerToken) -> Self {
+        Call::new(Method::POST, "/user/refresh").bearer(bearer)
+    }
+
+    fn lookup_user(bearer: &BearerToken, identifier: &str) -> Self {
+        let mut call = Call::new(Method::GET, "/user/lookup").bearer(bearer);
+        call.query.push(("identifier", identifier.to_string()));
+        call
+    }
+
+    fn create_guest(expires_after: Option<u64>) -> Self {
+        let mut call = Call::new(Method::POST, "/user/guest");
+        if let Some(expires_after) = expires_after {
+            call.query
+                .push(("expires_after", expires_after.to_string()));
+        }
+        call
+    }
+
+    fn upgrade_guest(bearer: &BearerToken, email: &str, username: &str, password: &str) -> Self {
+        Call::new(Method::POST, "/user/upgrade")
+            .bearer(bearer)
+            .json(&NewUser {
+                email,
+                username,
+                password,
+            })
+    }
+
+    fn preferences(bearer: &BearerToken) -> Self {
+        Call::new(Method::GET, "/user/preferences").bearer(bearer)
+    }
+
+    fn set_preferences(bearer: &BearerToken, preferences: &Map<String, Value>) -> Self {
+        Call::new(Method::PUT, "/user/preferences")
+            .bearer(bearer)
+            .json(preferences)
+    }
+
+    /// Authorizes the request with a bearer token
+    fn bearer(self, bearer: &BearerToken) -> Self {
+        self.authorization(Authorization::Bearer(bearer.clone()))
+    }
+
+    /// Sets the `Authorization` header, or marks the call invalid if the credentials can't be sent
+    fn authorization(mut self, authorization: Authorization) -> Self {
+        match authorization.try_into_value() {
+            Ok(value) => {
+                self.headers.insert(AUTHORIZATION, value);
+            }
+            Err(_) => return self.invalid("the credentials can not be sent in a header"),
+        }
+        self
+    }
+
+    /// Marks the call as one that can not be sent
+    fn invalid(mut self, reason: &str) -> Self {
+        self.invalid = Some(reason.to_string());
+        self
+    }
+
+    fn json(mut self, body: &impl Serialize) -> Self {
+        self.headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        self.body = Some(serde_json::to_vec(body).expect("request bodies serialize"));
+        self
+    }
+
+    /// Where to send the call, unless it can not be sent.
+    ///
+    /// The path is appended to the path of `host`, so the service can be reached under a prefix.
+    fn url(&self, host: &str) -> Result<Url, ClientError> {
+        if let Some(reason) = &self.invalid {
+            return Err(ClientError::InvalidRequest(reason.clone()));
+        }
+        let invalid_url = || ClientError::InvalidUrl(host.to_string());
+        let mut url = Url::from_str(host).map_err(|_| invalid_url())?;
+        url.path_segments_mut()
+            .map_err(|_| invalid_url())?
+            .pop_if_empty()
+            .extend(self.path.trim_start_matches('/').split('/'));
+        if !self.query.is_empty() {
+            url.query_pairs_mut().extend_pairs(&self.query);
+        }
+        Ok(url)
+    }
+
+    /// How long to wait before attempting the call again after `result`, if it should be
+    fn retry_delay(
+        &self,
+        retries: &Backoff,
+        attempt: u32,
+        result: &Result<Reply, reqwest::Error>,
+    ) -> Option<Duration> {
+        let idempotent = matches!(
+            self.method,
+            Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+        );
+        let transient = match result {
+            Ok(reply) => matches!(
+                reply.status,
+                StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            Err(e) => e.is_connect() || e.is_timeout(),
+        };
+        if !idempotent || !transient || retries.max_attempts.is_some_and(|max| attempt >= max) {
+            return None;
+        }
+
+        let delay = retries.jittered_delay(attempt);
+        warn!(
+            "{} {} failed (attempt {attempt}), retrying in {delay:?}",
+            self.method, self.path
+        );
+        Some(delay)
+    }
+}
+
+/// A response of the users service, read in full
+#[derive(Debug)]
+struct Reply {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    /// Turns error responses into errors
+    fn check(self) -> Result<Reply, ClientError> {
+        if self.status.is_success() {
+            return Ok(self);
+        }
+
+        let body = serde_json::from_slice(&self.body).unwrap_or_else(|_| ErrorBody {
+            code: ErrorCode::Unknown,
+            message: String::from_utf8_lossy(&self.body).into_owned(),
+        });
+        Err(ClientError::from_response(self.status, body))
+    }
+
+    /// Reads a json response, which is invalid if it does not match `T`
+    fn json<T: DeserializeOwned>(&self) -> Result<T, ClientError> {
+        serde_json::from_slice(&self.body).map_err(|e| ClientError::InvalidResponse(e.to_string()))
+    }
+
+    /// Reads the bearer token from the `Authorization` header
+    fn bearer(&self) -> Result<BearerToken, ClientError> {
+        let header = self
+            .headers
+            .get(AUTHORIZATION)
+            .ok_or_else(|| ClientError::InvalidResponse("no authorization header".to_string()))?;
+        let header = header
+            .to_str()
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        match header.parse() {
+            Ok(Authorization::Bearer(bearer)) => Ok(bearer),
+            Ok(_) => Err(ClientError::InvalidResponse(
+                "authorization header is not a bearer token".to_string(),
+            )),
+            Err(e) => Err(ClientError::InvalidResponse(e.to_string())),
+        }
+    }
+
+    fn guest_session(&self) -> Result<GuestSession, ClientError> {
+        let info: GuestInfo = self.json()?;
+        Ok(GuestSession {
+            id: info.id,
+            expires: info.expires,
+            bearer: self.bearer()?,
+        })
+    }
+
+    /// Reads the user of a profile, log in or upgrade, as seen by `client`
+    fn remote_user<C>(&self, client: &C) -> Result<RemoteUser<C>, ClientError>
+    where
+        C: Clone,
+    {
+        let info: UserInfo = self.json()?;
+        Ok(RemoteUser {
+            client: client.clone(),
+            username: info.username,
+            email: info.email,
+        })
+    }
+
+    /// Reads the user of a lookup, which is missing if the service found none
+    fn lookup<C>(
+        result: Result<Reply, ClientError>,
+        client: &C,
+    ) -> Result<Option<RemoteUser<C>>, ClientError>
+    where
+        C: Clone,
+    {
+        match result {
+            Ok(reply) => reply.remote_user(client).map(Some),
+            Err(error) if error.code() == Some(ErrorCode::UserNotFound) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Reads the user and bearer token of a log in, refresh or upgrade
+    fn authenticated<C>(&self, client: &C) -> Result<AuthenticatedRemoteUser<C>, ClientError>
+    where
+        C: Clone,
+    {
+        Ok(AuthenticatedRemoteUser {
+            remote_user: self.remote_user(client)?,
+            bearer: self.bearer()?,
+        })
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Client {
     host: String,
     client: reqwest::Client,
+    retries: Backoff,
 }
 
 impl Client {
+    /// A client with the default settings of [`ClientBuilder`]
     pub fn new(host: String) -> Self {
-        Self {
-            host,
-            client: reqwest::Client::default(),
+        Self::builder(host)
+            .build()
+            .expect("a client without tls files can be built")
+    }
+
+    pub fn builder(host: String) -> ClientBuilder {
+        ClientBuilder::new(host)
+    }
+
+    /// Creates an account
+    pub async fn sign_up(
+        &self,
+        email: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<RemoteUser, ClientError> {
+        self.send(Call::sign_up(email, username, password))
+            .await?
+            .remote_user(self)
+    }
+
+    /// Checks a bearer token, returning when it expires
+    pub async fn validate_token(
+        &self,
+        bearer: &BearerToken,
+    ) -> Result<ExpirationTime, ClientError> {
+        self.send(Call::validate_token(bearer)).await?.json()
+    }
+
+    /// Checks a bearer token, returning who it was issued to and what it grants
+    pub async fn claims(&self, bearer: &BearerToken) -> Result<Claims, ClientError> {
+        self.send(Call::claims(bearer)).await?.json()
+    }
+
+    /// Gets the user a bearer token was issued to
+    pub async fn profile(&self, bearer: &BearerToken) -> Result<RemoteUser, ClientError> {
+        self.send(Call::profile(bearer)).await?.remote_user(self)
+    }
+
+    /// Logs out of every session of the user a bearer token was issued to
+    pub async fn log_out(&self, bearer: &BearerToken) -> Result<(), ClientError> {
+        self.send(Call::log_out(bearer)).await?;
+        Ok(())
+    }
+
+    /// Issues a new bearer token to the user a bearer token was issued to
+    pub async fn refresh(
+        &self,
+        bearer: &BearerToken,
+    ) -> Result<AuthenticatedRemoteUser, ClientError> {
+        self.send(Call::refresh(bearer)).await?.authenticated(self)
+    }
+
+    /// Finds a user by email or username
+    pub async fn lookup_user(
+        &self,
+        bearer: &BearerToken,
+        identifier: &str,
+    ) -> Result<Option<RemoteUser>, ClientError> {
+        Reply::lookup(self.send(Call::lookup_user(bearer, identifier)).await, self)
+    }
+
+    /// Starts a guest session, lasting the given number of seconds or the service's default
+    pub async fn create_guest(
+        &self,
+        expires_after: Option<u64>,
+    ) -> Result<GuestSession, ClientError> {
+        self.send(Call::create_guest(expires_after))
+            .await?
+            .guest_session()
+    }
+
+    /// Turns the guest session of a bearer token into an account, keeping its preferences
+    pub async fn upgrade_guest(
+        &self,
+        bearer: &BearerToken,
+        email: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<AuthenticatedRemoteUser, ClientError> {
+        self.send(Call::upgrade_guest(bearer, email, username, password))
+            .await?
+            .authenticated(self)
+    }
+
+    /// Gets the preferences of the user or guest a bearer token was issued to
+    pub async fn preferences(
+        &self,
+        bearer: &BearerToken,
+    ) -> Result<Map<String, Value>, ClientError> {
+        self.send(Call::preferences(bearer)).await?.json()
+    }
+
+    /// Replaces the preferences of the user or guest a bearer token was issued to
+    pub async fn set_preferences(
+        &self,
+        bearer: &BearerToken,
+        preferences: &Map<String, Value>,
+    ) -> Result<(), ClientError> {
+        self.send(Call::set_preferences(bearer, preferences))
+            .await?;
+        Ok(())
+    }
+
+    /// Sends a call, retrying it if it is idempotent and failed in a way that may not last, and
+    /// turning error responses into errors
+    async fn send(&self, call: Call) -> Result<Reply, ClientError> {
+        let url = call.url(&self.host)?;
+        let mut attempt = 1;
+        loop {
+            let mut request = reqwest::Request::new(call.method.clone(), url.clone());
+            *request.headers_mut() = call.headers.clone();
+            *request.body_mut() = call.body.clone().map(Into::into);
+            let result = self.attempt(request).await;
+            match call.retry_delay(&self.retries, attempt, &result) {
+                Some(delay) => {
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return result?.check(),
+            }
         }
     }
+
+    async fn attempt(&self, request: reqwest::Request) -> Result<Reply, reqwest::Error> {
+        let response = self.client.execute(request).await?;
+        Ok(Reply {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: response.bytes().await?.to_vec(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct NewUser<'a> {
+    email: &'a str,
+    username: &'a str,
+    password: &'a str,
 }
-#[derive(Debug, serde::Deserialize)]
+
+#[derive(Debug, Deserialize)]
 struct UserInfo {
     username: String,
     email: EmailAddress,
 }
+
+#[derive(Debug, Deserialize)]
+struct GuestInfo {
+    id: String,
+    expires: ExpirationTime,
+}
+
 #[async_trait]
 impl UserService<RemoteUser> for Client {
     type Authenticated = AuthenticatedRemoteUser;
-    type AuthError = reqwest::Error;
+    type AuthError = ClientError;
+    type SignUpError = ClientError;
+    type LogOutError = ClientError;
+    type RefreshError = ClientError;
+    type CurrentUserError = ClientError;
+    type LookupError = ClientError;
+
+    async fn sign_up(
+        &self,
+        email: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<RemoteUser, Self::SignUpError> {
+        Client::sign_up(self, email, username, password).await
+    }
 
     async fn log_in(
         &self,
         user: &str,
         pass: &[u8],
     ) -> Result<Self::Authenticated, Self::AuthError> {
-        let response = self
-            .client
-            .post(
-                Url::from_str(&self.host)
-                    .unwrap()
-                    .join("/user/login")
-                    .unwrap(),
-            )
-            .header(
-                AUTHORIZATION,
-                format!(
-                    "Basic {}",
-                    encode_base64(&format!("{user}:{}", std::str::from_utf8(pass).unwrap()))
-                ),
-            )
-            .send()
-            .await?;
-        let auth = Authorization::from_str(&*String::from_utf8_lossy(
-            response.headers().get(AUTHORIZATION).unwrap().as_bytes(),
-        ))
-        .unwrap();
-        let info = response.json::<UserInfo>().await?;
+        self.send(Call::log_in(user, pass))
+            .await?
+            .authenticated(self)
+    }
 
-        let remote_user = RemoteUser {
-            client: self.clone(),
-            username: info.username,
-            email: info.email,
-        };
-        Ok(AuthenticatedRemoteUser {
-            remote_user,
-            bearer: auth.bearer().clone(),
-        })
+    async fn log_out(&self, bearer: &BearerToken) -> Result<(), Self::LogOutError> {
+        Client::log_out(self, bearer).await
+    }
+
+    async fn refresh(
+        &self,
+        bearer: &BearerToken,
+    ) -> Result<Self::Authenticated, Self::RefreshError> {
+        Client::refresh(self, bearer).await
+    }
+
+    async fn current_user(
+        &self,
+        bearer: &BearerToken,
+    ) -> Result<RemoteUser, Self::CurrentUserError> {
+        self.profile(bearer).await
+    }
+
+    async fn lookup_user(
+        &self,
+        bearer: &BearerToken,
+        identifier: &str,
+    ) -> Result<Option<RemoteUser>, Self::LookupError> {
+        Client::lookup_user(self, bearer, identifier).await
     }
 }
 
+/// A guest session
+#[derive(Debug, Clone)]
+pub struct GuestSession {
+    pub id: String,
+    pub expires: ExpirationTime,
+    pub bearer: BearerToken,
+}
+
+/// A user of the users service, retrieved with a [`Client`] or a [`blocking::Client`]
 #[derive(Debug)]
-pub struct RemoteUser {
-    client: Client,
+pub struct RemoteUser<C = Client> {
+    client: C,
     username: String,
     email: EmailAddress,
 }
 
-impl User for RemoteUser {
-    fn username(&self) -> &str {
-        &self.username
+impl<C> RemoteUser<C> {
+    /// The client this user was retrieved with
+    pub fn client(&self) -> &C {
+        &self.client
     }
+}
 
-    fn set_username(&mut self, name: &str) {
-        todo!("can not change username")
+impl<C> User for RemoteUser<C> {
+    fn username(&self) -> &str {
+        &self.username
     }
 
     fn email(&self) -> EmailAddress {
@@ -100,26 +742,385 @@ impl User for RemoteUser {
 }
 
 #[derive(Debug)]
-pub struct AuthenticatedRemoteUser {
-    remote_user: RemoteUser,
+pub struct AuthenticatedRemoteUser<C = Client> {
+    remote_user: RemoteUser<C>,
     bearer: BearerToken,
 }
 
-impl Deref for AuthenticatedRemoteUser {
-    type Target = RemoteUser;
+impl<C> Deref for AuthenticatedRemoteUser<C> {
+    type Target = RemoteUser<C>;
 
     fn deref(&self) -> &Self::Target {
         &self.remote_user
     }
 }
 
-impl DerefMut for AuthenticatedRemoteUser {
+impl<C> DerefMut for AuthenticatedRemoteUser<C> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.remote_user
     }
 }
-impl AuthenticatedUser<RemoteUser> for AuthenticatedRemoteUser {
+impl<C> AuthenticatedUser<RemoteUser<C>> for AuthenticatedRemoteUser<C> {
     fn bearer(&self) -> &BearerToken {
         &self.bearer
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::HeaderMap;
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use common::trace_context::{REQUEST_ID, TRACEPARENT};
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+    use openssl::x509::{X509NameBuilder, X509};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Logs in anyone, with the request id and traceparent it was sent as the username and email
+    async fn echo_login(req: HttpRequest) -> HttpResponse {
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_string())
+                .unwrap_or_default()
+        };
+        HttpResponse::Ok()
+            .insert_header((AUTHORIZATION, "Bearer token"))
+            .json(json!({
+                "username": header(REQUEST_ID),
+                "email": format!("{}@example.com", header(TRACEPARENT)),
+            }))
+    }
+
+    /// Starts a server with the given routes, returning its url
+    fn serve<F>(routes: F) -> String
+    where
+        F: Fn(&mut web::ServiceConfig) + Clone + Send + 'static,
+    {
+        let server = HttpServer::new(move || App::new().configure(routes.clone()))
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let host = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        host
+    }
+
+    /// Retries without waiting
+    fn attempts(max_attempts: u32) -> Backoff {
+        Backoff {
+            initial: Duration::ZERO,
+            max: Duration::ZERO,
+            max_attempts: Some(max_attempts),
+        }
+    }
+
+    /// A certificate for `127.0.0.1`, signed by `issuer` or else self-signed as an authority
+    fn certificate(
+        serial: u32,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+    ) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", &format!("test {serial}"))
+            .unwrap();
+        let name = name.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_serial_number(&BigNum::from_u32(serial).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_pubkey(key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let signer = match issuer {
+            Some((ca, ca_key)) => {
+                cert.set_issuer_name(ca.subject_name()).unwrap();
+                let san = SubjectAlternativeName::new()
+                    .ip("127.0.0.1")
+                    .build(&cert.x509v3_context(Some(ca), None))
+                    .unwrap();
+                cert.append_extension(san).unwrap();
+                ca_key
+            }
+            None => {
+                cert.set_issuer_name(&name).unwrap();
+                cert.append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                    .unwrap();
+                key
+            }
+        };
+        cert.sign(signer, MessageDigest::sha256()).unwrap();
+        cert.build()
+    }
+
+    /// Writes a certificate and its PKCS#8 key to `{name}.pem` and `{name}.key`
+    fn write_pem(dir: &Path, name: &str, cert: &X509, key: &PKey<Private>) -> (PathBuf, PathBuf) {
+        let cert_path = dir.join(format!("{name}.pem"));
+        let key_path = dir.join(format!("{name}.key"));
+        std::fs::write(&cert_path, cert.to_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        (cert_path, key_path)
+    }
+
+    fn ec_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn forwards_the_request_context() {
+        let host = serve(|cfg| {
+            cfg.route("/user/login", web::post().to(echo_login));
+        });
+
+        let client = Client::new(host);
+        let context = RequestContext::from_headers(&HeaderMap::new());
+        let user = context
+            .clone()
+            .scope(client.log_in("user", b"password"))
+            .await
+            .unwrap();
+
+        assert_eq!(user.username(), context.request_id());
+        assert_eq!(
+            user.email().as_ref(),
+            format!("{}@example.com", context.trace())
+        );
+    }
+
+    #[actix_web::test]
+    async fn sorts_error_responses() {
+        let host = serve(|cfg| {
+            cfg.route(
+                "/user/create",
+                web::post().to(|| async {
+                    HttpResponse::Conflict().json(ErrorBody::new(ErrorCode::UserExists, "taken"))
+                }),
+            )
+            .route(
+                "/user/me",
+                web::get().to(|| async {
+                    HttpResponse::Unauthorized()
+                        .json(ErrorBody::new(ErrorCode::TokenRevoked, "revoked"))
+                }),
+            )
+            .route(
+                "/user/logout",
+                web::post().to(|| async { HttpResponse::BadGateway().body("upstream down") }),
+            )
+            .route(
+                "/user/login",
+                web::post().to(|| async { HttpResponse::Ok().json(json!({})) }),
+            );
+        });
+        let client = Client::new(host);
+        let bearer = BearerToken::from("token");
+
+        let error = client
+            .sign_up("a@example.com", "a", "pass")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, ClientError::Validation(body) if body.code == ErrorCode::UserExists),
+            "{error:?}"
+        );
+
+        let error = client.profile(&bearer).await.unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::TokenRevoked));
+        assert!(matches!(error, ClientError::Auth(_)), "{error:?}");
+
+        let error = client.log_out(&bearer).await.unwrap_err();
+        assert!(
+            matches!(&error, ClientError::Server { status, body }
+                if *status == StatusCode::BAD_GATEWAY && body.message == "upstream down"),
+            "{error:?}"
+        );
+
+        let error = client.log_in("a", b"pass").await.unwrap_err();
+        assert!(
+            matches!(error, ClientError::InvalidResponse(_)),
+            "{error:?}"
+        );
+
+        let error = Client::new("not a url".to_string())
+            .log_out(&bearer)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ClientError::InvalidUrl(_)), "{error:?}");
+
+        // credentials that can't be put in a header are not sent without them
+        let error = client
+            .log_out(&BearerToken::from("to\nken"))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ClientError::InvalidRequest(_)), "{error:?}");
+        let error = client.log_in("a", &[0xff]).await.unwrap_err();
+        assert!(matches!(error, ClientError::InvalidRequest(_)), "{error:?}");
+    }
+
+    #[actix_web::test]
+    async fn keeps_the_path_of_the_host() {
+        let host = serve(|cfg| {
+            cfg.route(
+                "/users-service/user/me",
+                web::get().to(|| async {
+                    HttpResponse::Ok().json(json!({ "username": "a", "email": "a@example.com" }))
+                }),
+            );
+        });
+        let bearer = BearerToken::from("token");
+
+        for prefix in ["/users-service", "/users-service/"] {
+            let client = Client::new(format!("{host}{prefix}"));
+            let user = client.profile(&bearer).await.unwrap();
+            assert_eq!(user.username(), "a", "{prefix}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn retries_idempotent_requests() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let host = serve(move |cfg| {
+            let calls = counted.clone();
+            cfg.app_data(web::Data::from(calls))
+                .route(
+                    "/user/me",
+                    web::get().to(|calls: web::Data<AtomicUsize>| async move {
+                        if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                            HttpResponse::ServiceUnavailable().finish()
+                        } else {
+                            HttpResponse::Ok()
+                                .json(json!({ "username": "a", "email": "a@example.com" }))
+                        }
+                    }),
+                )
+                .route(
+                    "/user/logout",
+                    web::post().to(|calls: web::Data<AtomicUsize>| async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        HttpResponse::ServiceUnavailable().finish()
+                    }),
+                );
+        });
+        let bearer = BearerToken::from("token");
+
+        let client = Client::builder(host.clone())
+            .retries(attempts(3))
+            .build()
+            .unwrap();
+        let user = client.profile(&bearer).await.unwrap();
+        assert_eq!(user.username(), "a");
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 3);
+
+        let client = Client::builder(host).retries(attempts(2)).build().unwrap();
+        let error = client.profile(&bearer).await.unwrap_err();
+        assert!(
+            matches!(error, ClientError::Server { status, .. } if status == StatusCode::SERVICE_UNAVAILABLE),
+            "{error:?}"
+        );
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 2);
+
+        client.log_out(&bearer).await.unwrap_err();
+        assert_eq!(calls.load(Ordering::SeqCst), 1, "posts are not retried");
+    }
+
+    #[actix_web::test]
+    async fn requests_time_out() {
+        let host = serve(|cfg| {
+            cfg.route(
+                "/",
+                web::get().to(|| async {
+                    actix_web::rt::time::sleep(Duration::from_secs(5)).await;
+                    HttpResponse::Ok().finish()
+                }),
+            );
+        });
+        let client = Client::builder(host)
+            .timeout(Duration::from_millis(100))
+            .retries(attempts(1))
+            .build()
+            .unwrap();
+
+        let error = client
+            .validate_token(&BearerToken::from("token"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, ClientError::Transport(e) if e.is_timeout()),
+            "{error:?}"
+        );
+    }
+
+    #[actix_web::test]
+    async fn mutual_tls() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca_key = ec_key();
+        let ca = certificate(1, &ca_key, None);
+        let (capath, _) = write_pem(dir.path(), "ca", &ca, &ca_key);
+        let server_key = ec_key();
+        let server_cert = certificate(2, &server_key, Some((&ca, &ca_key)));
+        let (cert, cert_key) = write_pem(dir.path(), "server", &server_cert, &server_key);
+        let client_key = ec_key();
+        let client_cert = certificate(3, &client_key, Some((&ca, &ca_key)));
+        let (client_cert, client_key) = write_pem(dir.path(), "client", &client_cert, &client_key);
+
+        let tls = TlsConfig {
+            cert: Some(cert),
+            cert_key: Some(cert_key),
+            capath: Some(capath.clone()),
+            require_client_cert: true,
+        };
+        assert!(tls.validate().is_empty());
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/user/me",
+                web::get().to(|| async {
+                    HttpResponse::Ok().json(json!({ "username": "a", "email": "a@example.com" }))
+                }),
+            )
+        })
+        .workers(1)
+        .bind_openssl(("127.0.0.1", 0), tls.ssl_acceptor().unwrap())
+        .unwrap();
+        let host = format!("https://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        let bearer = BearerToken::from("token");
+
+        let anonymous = Client::builder(host.clone())
+            .root_ca(&capath)
+            .retries(attempts(1))
+            .build()
+            .unwrap();
+        let error = anonymous.profile(&bearer).await.unwrap_err();
+        assert!(matches!(error, ClientError::Transport(_)), "{error:?}");
+
+        let client = Client::builder(host)
+            .root_ca(&capath)
+            .client_cert(client_cert, client_key)
+            .build()
+            .unwrap();
+        assert_eq!(client.profile(&bearer).await.unwrap().username(), "a");
+    }
+
+    #[test]
+    fn missing_tls_files_are_reported() {
+        let error = Client::builder("https://localhost".to_string())
+            .root_ca("/does/not/exist.pem")
+            .build()
+            .unwrap_err();
+        assert!(matches!(error, ClientBuildError::Read { .. }), "{error:?}");
+    }
+}
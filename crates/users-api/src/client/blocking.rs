@@ -0,0 +1,262 @@
+//! A users service client for synchronous callers.
+//!
+//! It must not be used from within an async runtime, where [`super::Client`] should be used.
+
+use super::{
+    AuthenticatedRemoteUser, Call, ClientBuildError, ClientBuilder, ClientError, GuestSession,
+    RemoteUser, Reply,
+};
+use crate::bearer::BearerToken;
+use crate::claims::Claims;
+use crate::ExpirationTime;
+use common::retry::Backoff;
+use serde_json::{Map, Value};
+use std::thread;
+
+impl ClientBuilder {
+    pub fn build_blocking(self) -> Result<Client, ClientBuildError> {
+        let (root_ca, identity) = self.read_tls()?;
+        let mut client = reqwest::blocking::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.timeout);
+        if let Some(root_ca) = root_ca {
+            client = client.add_root_certificate(root_ca);
+        }
+        if let Some(identity) = identity {
+            client = client.identity(identity);
+        }
+
+        Ok(Client {
+            host: self.host,
+            client: client.build()?,
+            retries: self.retries,
+        })
+    }
+}
+
+/// The blocking version of [`super::Client`], with the same operations
+#[derive(Debug, Clone)]
+pub struct Client {
+    host: String,
+    client: reqwest::blocking::Client,
+    retries: Backoff,
+}
+
+impl Client {
+    /// A client with the default settings of [`ClientBuilder`]
+    pub fn new(host: String) -> Self {
+        ClientBuilder::new(host)
+            .build_blocking()
+            .expect("a client without tls files can be built")
+    }
+
+    /// Creates an account
+    pub fn sign_up(
+        &self,
+        email: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<RemoteUser<Client>, ClientError> {
+        self.send(Call::sign_up(email, username, password))?
+            .remote_user(self)
+    }
+
+    /// Logs in with a username and password
+    pub fn log_in(
+        &self,
+        user: &str,
+        pass: &[u8],
+    ) -> Result<AuthenticatedRemoteUser<Client>, ClientError> {
+        self.send(Call::log_in(user, pass))?.authenticated(self)
+    }
+
+    /// Checks a bearer token, returning when it expires
+    pub fn validate_token(&self, bearer: &BearerToken) -> Result<ExpirationTime, ClientError> {
+        self.send(Call::validate_token(bearer))?.json()
+    }
+
+    /// Checks a bearer token, returning who it was issued to and what it grants
+    pub fn claims(&self, bearer: &BearerToken) -> Result<Claims, ClientError> {
+        self.send(Call::claims(bearer))?.json()
+    }
+
+    /// Gets the user a bearer token was issued to
+    pub fn profile(&self, bearer: &BearerToken) -> Result<RemoteUser<Client>, ClientError> {
+        self.send(Call::profile(bearer))?.remote_user(self)
+    }
+
+    /// Logs out of every session of the user a bearer token was issued to
+    pub fn log_out(&self, bearer: &BearerToken) -> Result<(), ClientError> {
+        self.send(Call::log_out(bearer))?;
+        Ok(())
+    }
+
+    /// Issues a new bearer token to the user a bearer token was issued to
+    pub fn refresh(
+        &self,
+        bearer: &BearerToken,
+    ) -> Result<AuthenticatedRemoteUser<Client>, ClientError> {
+        self.send(Call::refresh(bearer))?.authenticated(self)
+    }
+
+    /// Finds a user by email or username
+    pub fn lookup_user(
+        &self,
+        bearer: &BearerToken,
+        identifier: &str,
+    ) -> Result<Option<RemoteUser<Client>>, ClientError> {
+        Reply::lookup(self.send(Call::lookup_user(bearer, identifier)), self)
+    }
+
+    /// Starts a guest session, lasting the given number of seconds or the service's default
+    pub fn create_guest(&self, expires_after: Option<u64>) -> Result<GuestSession, ClientError> {
+        self.send(Call::create_guest(expires_after))?
+            .guest_session()
+    }
+
+    /// Turns the guest session of a bearer token into an account, keeping its preferences
+    pub fn upgrade_guest(
+        &self,
+        bearer: &BearerToken,
+        email: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<AuthenticatedRemoteUser<Client>, ClientError> {
+        self.send(Call::upgrade_guest(bearer, email, username, password))?
+            .authenticated(self)
+    }
+
+    /// Gets the preferences of the user or guest a bearer token was issued to
+    pub fn preferences(&self, bearer: &BearerToken) -> Result<Map<String, Value>, ClientError> {
+        self.send(Call::preferences(bearer))?.json()
+    }
+
+    /// Replaces the preferences of the user or guest a bearer token was issued to
+    pub fn set_preferences(
+        &self,
+        bearer: &BearerToken,
+        preferences: &Map<String, Value>,
+    ) -> Result<(), ClientError> {
+        self.send(Call::set_preferences(bearer, preferences))?;
+        Ok(())
+    }
+
+    /// Sends a call like [`super::Client`] does, sleeping the current thread between attempts
+    fn send(&self, call: Call) -> Result<Reply, ClientError> {
+        let url = call.url(&self.host)?;
+        let mut attempt = 1;
+        loop {
+            let mut request = reqwest::blocking::Request::new(call.method.clone(), url.clone());
+            *request.headers_mut() = call.headers.clone();
+            *request.body_mut() = call.body.clone().map(Into::into);
+            let result = self.attempt(request);
+            match call.retry_delay(&self.retries, attempt, &result) {
+                Some(delay) => {
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                None => return result?.check(),
+            }
+        }
+    }
+
+    fn attempt(&self, request: reqwest::blocking::Request) -> Result<Reply, reqwest::Error> {
+        let response = self.client.execute(request)?;
+        Ok(Reply {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: response.bytes()?.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{ErrorBody, ErrorCode};
+    use crate::User;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use serde_json::json;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// Runs a server on its own thread, as blocking clients can not be used within its runtime
+    fn serve(routes: fn(&mut web::ServiceConfig)) -> String {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                let server = HttpServer::new(move || App::new().configure(routes))
+                    .workers(1)
+                    .bind(("127.0.0.1", 0))
+                    .unwrap();
+                sender
+                    .send(format!("http://{}", server.addrs()[0]))
+                    .unwrap();
+                server.run().await
+            })
+        });
+        receiver.recv().unwrap()
+    }
+
+    #[test]
+    fn has_the_same_operations() {
+        let host = serve(|cfg| {
+            let logged_in = || async {
+                HttpResponse::Ok()
+                    .insert_header(("Authorization", "Bearer token"))
+                    .json(json!({ "username": "a", "email": "a@example.com" }))
+            };
+            cfg.route("/user/login", web::post().to(logged_in))
+                .route("/user/refresh", web::post().to(logged_in))
+                .route(
+                    "/user/create",
+                    web::post().to(|| async {
+                        HttpResponse::Ok()
+                            .json(json!({ "username": "b", "email": "b@example.com" }))
+                    }),
+                )
+                .route(
+                    "/user/lookup",
+                    web::get().to(|| async {
+                        HttpResponse::NotFound()
+                            .json(ErrorBody::new(ErrorCode::UserNotFound, "no user"))
+                    }),
+                )
+                .route(
+                    "/user/preferences",
+                    web::get().to(|| async { HttpResponse::Ok().json(json!({ "theme": "dark" })) }),
+                )
+                .route(
+                    "/user/guest",
+                    web::post().to(|| async {
+                        HttpResponse::ServiceUnavailable()
+                            .json(ErrorBody::new(ErrorCode::Internal, "down"))
+                    }),
+                );
+        });
+        let client = ClientBuilder::new(host)
+            .retries(Backoff {
+                initial: Duration::ZERO,
+                max: Duration::ZERO,
+                max_attempts: Some(2),
+            })
+            .build_blocking()
+            .unwrap();
+
+        let user = client.log_in("a", b"password").unwrap();
+        assert_eq!(user.username(), "a");
+        assert_eq!(user.bearer, BearerToken::from("token"));
+        let refreshed = client.refresh(&user.bearer).unwrap();
+        assert_eq!(refreshed.bearer, user.bearer);
+        let created = client.sign_up("b@example.com", "b", "password").unwrap();
+        assert_eq!(created.username(), "b");
+        assert!(client.lookup_user(&user.bearer, "c").unwrap().is_none());
+        let preferences = client.preferences(&user.bearer).unwrap();
+        assert_eq!(preferences["theme"], "dark");
+
+        let error = client.create_guest(None).unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::Internal));
+        let error = client.profile(&user.bearer).unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::Unknown));
+    }
+}
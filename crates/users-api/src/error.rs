@@ -1,8 +1,9 @@
 use crate::auth::PasswordError;
+use crate::scope::Scope;
 use actix_web::http::StatusCode;
-use actix_web::ResponseError;
+use actix_web::{HttpResponse, ResponseError};
 use chrono::{DateTime, Utc};
-use std::time::Instant;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, thiserror::Error)]
 pub enum AuthError {
@@ -14,14 +15,102 @@ pub enum AuthError {
     TokenParseError,
     #[error("The token could not be verified")]
     VerificationError,
+    #[error("The token has been revoked")]
+    TokenRevoked,
+    #[error("The token does not grant the {0} scope")]
+    MissingScope(Scope),
     #[error(transparent)]
     JwtError(#[from] jwt::Error),
     #[error(transparent)]
     PasswordError(#[from] PasswordError),
+    /// The users service rejected the token
+    #[error("{}", .0.message)]
+    Rejected(ErrorBody),
+    /// The users service could not be asked whether the token is valid
+    #[error("could not validate the token: {0}")]
+    Unavailable(String),
+}
+
+impl AuthError {
+    /// What kind of auth error this is
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AuthError::NoUserFound(_) | AuthError::PasswordError(_) => {
+                ErrorCode::InvalidCredentials
+            }
+            AuthError::TokenExpired(_) => ErrorCode::TokenExpired,
+            AuthError::TokenRevoked => ErrorCode::TokenRevoked,
+            AuthError::MissingScope(_) => ErrorCode::MissingScope,
+            AuthError::TokenParseError | AuthError::VerificationError | AuthError::JwtError(_) => {
+                ErrorCode::InvalidToken
+            }
+            AuthError::Rejected(body) => body.code,
+            AuthError::Unavailable(_) => ErrorCode::Internal,
+        }
+    }
 }
 
 impl ResponseError for AuthError {
     fn status_code(&self) -> StatusCode {
-        StatusCode::UNAUTHORIZED
+        match self {
+            AuthError::MissingScope(_) => StatusCode::FORBIDDEN,
+            AuthError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        ErrorBody::new(self.code(), self).into_response(self.status_code())
+    }
+}
+
+/// What went wrong, as sent in the body of an error response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request was malformed, or its values were not acceptable
+    InvalidRequest,
+    /// The user does not exist, or the password is wrong
+    InvalidCredentials,
+    /// The bearer token is missing or could not be verified
+    InvalidToken,
+    /// The bearer token has expired
+    TokenExpired,
+    /// The bearer token was revoked by logging out
+    TokenRevoked,
+    /// The bearer token does not grant the scope needed
+    MissingScope,
+    /// The user has been banned
+    Banned,
+    /// A user already exists with the email or username
+    UserExists,
+    /// No user exists with the email or username
+    UserNotFound,
+    /// Something went wrong on the server
+    Internal,
+    /// A code this version does not know about
+    #[serde(other)]
+    Unknown,
+}
+
+/// The json body of every error response
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorBody {
+    /// Creates an error body with the message of an error
+    pub fn new(code: ErrorCode, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    /// A json response with this body
+    pub fn into_response(self, status: StatusCode) -> HttpResponse {
+        HttpResponse::build(status).json(self)
     }
 }
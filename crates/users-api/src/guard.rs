@@ -1,30 +1,31 @@
 use crate::auth::AuthService;
-use crate::bearer::BearerToken;
+use crate::authentication::Principal;
 use crate::error::AuthError;
 use crate::header::Authorization;
-use crate::ExpirationTime;
+use crate::token_cache::TokenCache;
 use actix_web::guard::{Guard, GuardContext};
-use log::error;
-use parking_lot::RwLock;
-use reqwest::Url;
-use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::{Instant, SystemTime};
+use tracing::{debug, error};
 
-/// Middle ware checker
+/// Middle ware checker.
+///
+/// Guards are synchronous, so a request behind the
+/// [`Authentication`](crate::authentication::Authentication) middleware passes with the validation
+/// the middleware already made of its token on the blocking thread pool. Otherwise the cached
+/// validation is used, and a token missing from the cache is validated on the worker handling the
+/// request, unless the auth service [blocks](AuthService::blocks). A
+/// [`RemoteAuthService`](crate::auth::RemoteAuthService) does, so routes it guards must be behind
+/// the middleware.
 #[derive(Debug)]
 pub struct AuthorizationGuard<A: AuthService> {
-    validated_tokens: Arc<RwLock<HashMap<BearerToken, ExpirationTime>>>,
+    cache: Arc<TokenCache>,
     auth_endpoint: A,
 }
 
 impl<A: AuthService> AuthorizationGuard<A> {
-    pub fn new(
-        validated_tokens: Arc<RwLock<HashMap<BearerToken, ExpirationTime>>>,
-        auth_endpoint: A,
-    ) -> Self {
+    pub fn new(cache: Arc<TokenCache>, auth_endpoint: A) -> Self {
         Self {
-            validated_tokens,
+            cache,
             auth_endpoint,
         }
     }
@@ -32,36 +33,95 @@ impl<A: AuthService> AuthorizationGuard<A> {
 
 impl<A: AuthService> Guard for AuthorizationGuard<A> {
     fn check(&self, ctx: &GuardContext<'_>) -> bool {
-        match ctx.header::<Authorization>() {
-            None => false,
-            Some(auth) => {
-                let bearer = auth.bearer();
+        if let Some(principal) = ctx.req_data().get::<Principal>() {
+            return matches!(principal, Principal::Valid(_));
+        }
+        let Some(bearer) = ctx
+            .header::<Authorization>()
+            .and_then(Authorization::into_bearer)
+        else {
+            return false;
+        };
+        let result = match self.cache.cached(&bearer) {
+            Some(result) => result,
+            None if self.auth_endpoint.blocks() => {
+                error!("the token was not validated, the app is missing the Authentication middleware");
+                return false;
+            }
+            None => self
+                .cache
+                .validate(&bearer, |bearer| self.auth_endpoint.claims(bearer)),
+        };
+        match result {
+            Ok(_) => true,
+            Err(error @ AuthError::Unavailable(_)) => {
+                error!("auth error: {}", error);
+                false
+            }
+            Err(error) => {
+                debug!("rejected token: {}", error);
+                false
+            }
+        }
+    }
+}
 
-                let mut remove = false;
-                if let Some(expires) = self.validated_tokens.read().get(bearer) {
-                    if expires < &ExpirationTime::from(SystemTime::now()) {
-                        return true;
-                    } else {
-                        remove = true;
-                    }
-                }
-                if remove {
-                    self.validated_tokens.write().remove(bearer);
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{local, LocalAuthService};
+    use crate::bearer::BearerToken;
+    use crate::ExpirationTime;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use serde_json::json;
+    use std::time::SystemTime;
 
-                match self.auth_endpoint.validate_token(bearer) {
-                    Ok(expires) => {
-                        self.validated_tokens
-                            .write()
-                            .insert(bearer.clone(), expires);
-                        true
-                    }
-                    Err(error) => {
-                        error!("auth error: {}", error);
-                        false
-                    }
-                }
+    fn token(expiration_time: ExpirationTime) -> BearerToken {
+        let claims = json!({
+            "subject": "a@example.com",
+            "scope": "user",
+            "expiration_time": expiration_time,
+        });
+        local::sign(b"secret", &claims)
+    }
+
+    #[actix_web::test]
+    async fn expired_tokens_do_not_pass() {
+        let cache = Arc::new(TokenCache::new(10));
+        let guarded = cache.clone();
+        let app = init_service(
+            App::new().route(
+                "/",
+                web::get()
+                    .guard(AuthorizationGuard::new(
+                        guarded,
+                        LocalAuthService::new(b"secret"),
+                    ))
+                    .to(HttpResponse::Ok),
+            ),
+        )
+        .await;
+        let request = |bearer: Option<&BearerToken>| {
+            let mut req = TestRequest::get().uri("/");
+            if let Some(bearer) = bearer {
+                req = req.insert_header(Authorization::Bearer(bearer.clone()));
             }
+            req.to_request()
+        };
+
+        let now = ExpirationTime::from(SystemTime::now());
+        let live = token(now + chrono::Duration::hours(1));
+        let expired = token(now - chrono::Duration::hours(1));
+        for _ in 0..2 {
+            let resp = call_service(&app, request(Some(&live))).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let resp = call_service(&app, request(Some(&expired))).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
         }
+        let resp = call_service(&app, request(None)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(cache.len(), 2);
     }
 }
@@ -4,10 +4,16 @@ use chrono::{DateTime, Utc};
 pub use email_address::EmailAddress;
 
 pub mod auth;
+pub mod authentication;
 pub mod bearer;
+pub mod claims;
 pub mod error;
 pub mod guard;
 pub mod header;
+pub mod scope;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod token_cache;
 pub mod user_service;
 pub mod client;
 
@@ -18,8 +24,6 @@ pub type ExpirationTime = DateTime<Utc>;
 pub trait User {
     /// Gets the username of the user
     fn username(&self) -> &str;
-    /// Sets the username of this user
-    fn set_username(&mut self, name: &str);
 
     /// Gets the email of the user
     fn email(&self) -> EmailAddress;
@@ -0,0 +1,23 @@
+//! Scopes granted by a bearer token
+
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// What a bearer token is allowed to do
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// A full, registered account
+    User,
+    /// An anonymous visitor. Guests can browse and keep preferences, but nothing else.
+    Guest,
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scope::User => write!(f, "user"),
+            Scope::Guest => write!(f, "guest"),
+        }
+    }
+}
@@ -0,0 +1,423 @@
+//! Stand-ins for the users service, for the tests of services that depend on it.
+//!
+//! Enabled with the `test-util` feature.
+//!
+//! - [`mint_token`] signs claims as the users service does, so they are accepted by a
+//!   [`LocalAuthService`] sharing the secret
+//! - [`FakeUsers`] is an in-process [`UserService`] and [`AuthService`] with seeded users
+//!   and users that signed up
+
+use crate::auth::local::sign;
+use crate::auth::{AuthService, LocalAuthService, PasswordError};
+use crate::bearer::BearerToken;
+use crate::claims::Claims;
+use crate::error::{AuthError, ErrorBody, ErrorCode};
+use crate::scope::Scope;
+use crate::user_service::{AuthenticatedUser, UserService};
+use crate::{EmailAddress, User};
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use common::secret::Secret;
+use parking_lot::Mutex;
+use rand::RngCore;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// How many hours the tokens issued by [`FakeUsers`] are valid
+const TOKEN_HOURS: i64 = 1;
+
+/// Signs a token with the given claims, as the users service would.
+///
+/// # Panics
+/// Panics unless the claims grant exactly one scope, as tokens of the users service do.
+pub fn mint_token(secret: &[u8], claims: &Claims) -> BearerToken {
+    let [scope] = claims.scopes[..] else {
+        panic!("tokens grant exactly one scope, not {:?}", claims.scopes);
+    };
+    let mut token = json!({
+        "subject": claims.subject,
+        "scope": scope,
+        "expiration_time": claims.expires,
+        "issued_at": Utc::now(),
+    });
+    if let Some(user_id) = claims.user_id {
+        token["user_id"] = json!(user_id);
+    }
+    if let Some(username) = &claims.username {
+        token["username"] = json!(username);
+    }
+    sign(secret, &token)
+}
+
+/// A user seeded into [`FakeUsers`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FakeUser {
+    id: i64,
+    username: String,
+    email: EmailAddress,
+}
+
+impl FakeUser {
+    /// The id of the user, in the order they were seeded starting from 1
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// The claims of a token issued to this user
+    fn claims(&self) -> Claims {
+        Claims {
+            subject: self.email.to_string(),
+            user_id: Some(self.id),
+            username: Some(self.username.clone()),
+            scopes: vec![Scope::User],
+            expires: Utc::now() + Duration::hours(TOKEN_HOURS),
+        }
+    }
+}
+
+impl User for FakeUser {
+    fn username(&self) -> &str {
+        &self.username
+    }
+
+    fn email(&self) -> EmailAddress {
+        self.email.clone()
+    }
+}
+
+/// A [`FakeUser`] that logged in
+#[derive(Debug)]
+pub struct AuthenticatedFakeUser {
+    user: FakeUser,
+    bearer: BearerToken,
+}
+
+impl Deref for AuthenticatedFakeUser {
+    type Target = FakeUser;
+
+    fn deref(&self) -> &Self::Target {
+        &self.user
+    }
+}
+
+impl DerefMut for AuthenticatedFakeUser {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.user
+    }
+}
+
+impl AuthenticatedUser<FakeUser> for AuthenticatedFakeUser {
+    fn bearer(&self) -> &BearerToken {
+        &self.bearer
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    users: Vec<(FakeUser, Secret<String>)>,
+    /// The tokens issued to each user, revoked when they log out
+    issued: HashMap<i64, Vec<BearerToken>>,
+    revoked: HashSet<BearerToken>,
+}
+
+impl State {
+    fn find(&self, identifier: &str) -> Option<&(FakeUser, Secret<String>)> {
+        self.users
+            .iter()
+            .find(|(user, _)| user.username == identifier || user.email.as_ref() == identifier)
+    }
+
+    fn add(&mut self, email: EmailAddress, username: &str, password: &str) -> FakeUser {
+        let user = FakeUser {
+            id: self.users.len() as i64 + 1,
+            username: username.to_string(),
+            email,
+        };
+        self.users
+            .push((user.clone(), Secret::new(password.to_string())));
+        user
+    }
+
+    fn issue(&mut self, secret: &[u8], user: &FakeUser) -> BearerToken {
+        let token = mint_token(secret, &user.claims());
+        self.issued.entry(user.id).or_default().push(token.clone());
+        token
+    }
+}
+
+/// An in-process users service, with users seeded by [`with_user`](FakeUsers::with_user).
+///
+/// Its tokens are signed with a random secret, which a [`LocalAuthService`] can share through
+/// [`secret`](FakeUsers::secret). Clones share their users and revoked tokens.
+#[derive(Debug, Clone)]
+pub struct FakeUsers {
+    secret: Secret<Vec<u8>>,
+    auth: LocalAuthService,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeUsers {
+    pub fn new() -> Self {
+        let mut secret = vec![0; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Self {
+            auth: LocalAuthService::new(&secret),
+            secret: Secret::new(secret),
+            state: Arc::default(),
+        }
+    }
+
+    /// Seeds a user who can log in with the given password.
+    ///
+    /// # Panics
+    /// Panics if the email is not valid.
+    pub fn with_user(self, email: &str, username: &str, password: &str) -> Self {
+        let email = EmailAddress::from_str(email).expect("seeded users need a valid email");
+        self.state.lock().add(email, username, password);
+        self
+    }
+
+    /// The secret the tokens are signed with
+    pub fn secret(&self) -> &[u8] {
+        self.secret.expose_secret()
+    }
+
+    /// Finds a seeded user by email or username
+    pub fn user(&self, identifier: &str) -> Option<FakeUser> {
+        self.state
+            .lock()
+            .find(identifier)
+            .map(|(user, _)| user.clone())
+    }
+
+    /// Issues a token to a seeded user, without their password.
+    ///
+    /// # Panics
+    /// Panics if no user was seeded with the email or username.
+    pub fn token_for(&self, identifier: &str) -> BearerToken {
+        let user = self
+            .user(identifier)
+            .unwrap_or_else(|| panic!("no user was seeded as {identifier:?}"));
+        self.state.lock().issue(self.secret(), &user)
+    }
+
+    /// Issues a token to a guest
+    pub fn guest_token(&self, id: &str) -> BearerToken {
+        let claims = Claims {
+            subject: id.to_string(),
+            user_id: None,
+            username: None,
+            scopes: vec![Scope::Guest],
+            expires: Utc::now() + Duration::hours(TOKEN_HOURS),
+        };
+        mint_token(self.secret(), &claims)
+    }
+
+    /// Revokes a token, as logging out would
+    pub fn revoke(&self, token: &BearerToken) {
+        self.state.lock().revoked.insert(token.clone());
+    }
+
+    /// The user a valid token was issued to
+    fn token_user(&self, token: &BearerToken) -> Result<FakeUser, AuthError> {
+        let claims = self.claims(token)?;
+        let user_id = claims.user_id.ok_or(AuthError::MissingScope(Scope::User))?;
+        self.state
+            .lock()
+            .users
+            .iter()
+            .find(|(user, _)| user.id == user_id)
+            .map(|(user, _)| user.clone())
+            .ok_or(AuthError::NoUserFound(claims.subject))
+    }
+}
+
+impl Default for FakeUsers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AuthService for FakeUsers {
+    fn claims(&self, token: &BearerToken) -> Result<Claims, AuthError> {
+        if self.state.lock().revoked.contains(token) {
+            return Err(AuthError::TokenRevoked);
+        }
+        self.auth.claims(token)
+    }
+}
+
+#[async_trait]
+impl UserService<FakeUser> for FakeUsers {
+    type Authenticated = AuthenticatedFakeUser;
+    type AuthError = AuthError;
+    type SignUpError = AuthError;
+    type LogOutError = AuthError;
+    type RefreshError = AuthError;
+    type CurrentUserError = AuthError;
+    type LookupError = AuthError;
+
+    async fn sign_up(
+        &self,
+        email: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<FakeUser, Self::SignUpError> {
+        let rejected = |code, message: &str| AuthError::Rejected(ErrorBody::new(code, message));
+        let email = EmailAddress::from_str(email)
+            .map_err(|_| rejected(ErrorCode::InvalidRequest, "invalid email"))?;
+        let mut state = self.state.lock();
+        if state.find(username).is_some() || state.find(email.as_ref()).is_some() {
+            return Err(rejected(ErrorCode::UserExists, "user already exists"));
+        }
+        Ok(state.add(email, username, password))
+    }
+
+    async fn log_in(
+        &self,
+        user: &str,
+        pass: &[u8],
+    ) -> Result<Self::Authenticated, Self::AuthError> {
+        let user = {
+            let state = self.state.lock();
+            let (user, password) = state
+                .find(user)
+                .ok_or_else(|| AuthError::NoUserFound(user.to_string()))?;
+            if password.expose_secret().as_bytes() != pass {
+                return Err(PasswordError::IncorrectPassword.into());
+            }
+            user.clone()
+        };
+        Ok(AuthenticatedFakeUser {
+            bearer: self.state.lock().issue(self.secret(), &user),
+            user,
+        })
+    }
+
+    async fn log_out(&self, bearer: &BearerToken) -> Result<(), Self::LogOutError> {
+        let user = self.token_user(bearer)?;
+        let mut state = self.state.lock();
+        let issued = state.issued.remove(&user.id).unwrap_or_default();
+        state.revoked.extend(issued);
+        state.revoked.insert(bearer.clone());
+        Ok(())
+    }
+
+    async fn refresh(
+        &self,
+        bearer: &BearerToken,
+    ) -> Result<Self::Authenticated, Self::RefreshError> {
+        let user = self.token_user(bearer)?;
+        Ok(AuthenticatedFakeUser {
+            bearer: self.state.lock().issue(self.secret(), &user),
+            user,
+        })
+    }
+
+    async fn current_user(&self, bearer: &BearerToken) -> Result<FakeUser, Self::CurrentUserError> {
+        self.token_user(bearer)
+    }
+
+    async fn lookup_user(
+        &self,
+        bearer: &BearerToken,
+        identifier: &str,
+    ) -> Result<Option<FakeUser>, Self::LookupError> {
+        self.token_user(bearer)?;
+        Ok(self.user(identifier))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+
+    fn users() -> FakeUsers {
+        FakeUsers::new()
+            .with_user("a@example.com", "a", "password")
+            .with_user("b@example.com", "b", "hunter2")
+    }
+
+    #[actix_web::test]
+    async fn seeded_users_log_in() {
+        let users = users();
+        let b = users.log_in("b@example.com", b"hunter2").await.unwrap();
+        assert_eq!((b.id(), b.username()), (2, "b"));
+        let claims = users.claims(b.bearer()).unwrap();
+        assert_eq!(claims.user_id, Some(2));
+        assert_eq!(claims.scopes, [Scope::User]);
+
+        let error = users.log_in("a", b"hunter2").await.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InvalidCredentials);
+        let error = users.log_in("c", b"password").await.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InvalidCredentials);
+    }
+
+    #[actix_web::test]
+    async fn sessions_end_on_log_out() {
+        let users = users();
+        let c = users.sign_up("c@example.com", "c", "secret").await.unwrap();
+        assert_eq!(c.id(), 3);
+        let error = users
+            .sign_up("a@example.com", "d", "secret")
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::UserExists);
+
+        let first = users.log_in("c", b"secret").await.unwrap();
+        let second = users.refresh(first.bearer()).await.unwrap();
+        assert_eq!(users.current_user(second.bearer()).await.unwrap(), c);
+        let found = users
+            .lookup_user(second.bearer(), "b@example.com")
+            .await
+            .unwrap();
+        assert_eq!(found.map(|user| user.id()), Some(2));
+        assert_eq!(users.lookup_user(second.bearer(), "d").await.unwrap(), None);
+        let guest = users.guest_token("guest");
+        assert!(matches!(
+            users.current_user(&guest).await,
+            Err(AuthError::MissingScope(Scope::User))
+        ));
+
+        // every token of the user is revoked, but not those of other users
+        let a = users.token_for("a");
+        users.log_out(first.bearer()).await.unwrap();
+        for token in [first.bearer(), second.bearer()] {
+            assert!(matches!(users.claims(token), Err(AuthError::TokenRevoked)));
+        }
+        assert!(users.claims(&a).is_ok());
+    }
+
+    #[test]
+    fn tokens_are_validated() {
+        let users = users();
+        let token = users.token_for("a");
+        assert_eq!(users.claims(&token).unwrap().subject, "a@example.com");
+        let guest = users.guest_token("guest");
+        assert_eq!(users.claims(&guest).unwrap().scopes, [Scope::Guest]);
+
+        // clones share the revoked tokens, and other services share the secret
+        users.clone().revoke(&token);
+        assert!(matches!(users.claims(&token), Err(AuthError::TokenRevoked)));
+        let local = LocalAuthService::new(users.secret());
+        assert!(local.claims(&token).is_ok());
+        assert!(FakeUsers::new().claims(&guest).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "exactly one scope")]
+    fn minted_tokens_have_one_scope() {
+        let claims = Claims {
+            subject: "a".to_string(),
+            user_id: None,
+            username: None,
+            scopes: vec![],
+            expires: Utc::now(),
+        };
+        mint_token(b"secret", &claims);
+    }
+}
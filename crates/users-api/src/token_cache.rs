@@ -0,0 +1,397 @@
+//! The cache of token validations used by [`Authentication`](crate::authentication::Authentication)
+//! and [`AuthorizationGuard`](crate::guard::AuthorizationGuard)
+
+use crate::bearer::BearerToken;
+use crate::claims::Claims;
+use crate::error::{AuthError, ErrorBody};
+use crate::ExpirationTime;
+use common::metrics::counter_vec;
+use parking_lot::Mutex;
+use prometheus::IntCounterVec;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+
+struct CacheMetrics {
+    lookups: IntCounterVec,
+    evictions: IntCounterVec,
+}
+
+fn metrics() -> &'static CacheMetrics {
+    static METRICS: OnceLock<CacheMetrics> = OnceLock::new();
+    METRICS.get_or_init(|| CacheMetrics {
+        lookups: counter_vec(
+            "auth_token_cache_total",
+            "Lookups of bearer tokens in the token cache, by result",
+            &["result"],
+        ),
+        evictions: counter_vec(
+            "auth_token_cache_evictions_total",
+            "Validations dropped from the token cache, by reason",
+            &["reason"],
+        ),
+    })
+}
+
+/// How a token was validated
+#[derive(Debug, Clone)]
+enum Validation {
+    Valid(Claims),
+    Rejected(ErrorBody),
+}
+
+#[derive(Debug)]
+struct Entry {
+    validation: Validation,
+    cached_at: Instant,
+    /// When the entry was last used, as a tick of [`Entries::order`]
+    used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    by_token: HashMap<BearerToken, Entry>,
+    /// The tokens cached, from least to most recently used
+    order: BTreeMap<u64, BearerToken>,
+    tick: u64,
+}
+
+impl Entries {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn remove(&mut self, token: &BearerToken) -> Option<Entry> {
+        let entry = self.by_token.remove(token)?;
+        self.order.remove(&entry.used);
+        Some(entry)
+    }
+}
+
+/// A bounded cache of token validations, evicting the least recently used ones.
+///
+/// A valid token is trusted until it expires, but at most for `ttl` after it was validated, so
+/// that a revoked token is rejected soon after. A rejected token is rejected again without being
+/// validated for `negative_ttl`. Validations that could not be made are not cached.
+#[derive(Debug)]
+pub struct TokenCache {
+    entries: Mutex<Entries>,
+    capacity: usize,
+    ttl: Duration,
+    negative_ttl: Duration,
+}
+
+impl TokenCache {
+    /// Creates a cache of at most `capacity` tokens, trusting valid tokens for a minute and
+    /// remembering rejected ones for 10 seconds
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::default(),
+            capacity,
+            ttl: Duration::from_secs(60),
+            negative_ttl: Duration::from_secs(10),
+        }
+    }
+
+    /// How long a valid token is trusted before being validated again
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// How long a rejected token is rejected before being validated again
+    pub fn with_negative_ttl(mut self, negative_ttl: Duration) -> Self {
+        self.negative_ttl = negative_ttl;
+        self
+    }
+
+    /// The number of validations cached
+    pub fn len(&self) -> usize {
+        self.entries.lock().by_token.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets the validation of a token, such as one that was just logged out
+    pub fn invalidate(&self, token: &BearerToken) {
+        self.entries.lock().remove(token);
+    }
+
+    /// Forgets every validation
+    pub fn clear(&self) {
+        *self.entries.lock() = Entries::default();
+    }
+
+    /// Returns the cached validation of a token, if it can still be relied on
+    pub fn cached(&self, token: &BearerToken) -> Option<Result<Claims, AuthError>> {
+        Some(match self.lookup(token)? {
+            Validation::Valid(claims) => {
+                metrics().lookups.with_label_values(&["hit"]).inc();
+                Ok(claims)
+            }
+            Validation::Rejected(body) => {
+                metrics().lookups.with_label_values(&["negative_hit"]).inc();
+                Err(AuthError::Rejected(body))
+            }
+        })
+    }
+
+    /// Returns the cached validation of a token, or validates it with `validate` and caches the
+    /// result
+    pub fn validate(
+        &self,
+        token: &BearerToken,
+        validate: impl FnOnce(&BearerToken) -> Result<Claims, AuthError>,
+    ) -> Result<Claims, AuthError> {
+        if let Some(result) = self.cached(token) {
+            return result;
+        }
+        metrics().lookups.with_label_values(&["miss"]).inc();
+
+        let result = validate(token);
+        match &result {
+            Ok(claims) => self.insert(token, Validation::Valid(claims.clone())),
+            Err(AuthError::Unavailable(_)) => {}
+            Err(error) => self.insert(
+                token,
+                Validation::Rejected(ErrorBody::new(error.code(), error)),
+            ),
+        }
+        result
+    }
+
+    /// Finds a validation that can still be relied on, dropping it if it can not
+    fn lookup(&self, token: &BearerToken) -> Option<Validation> {
+        let mut entries = self.entries.lock();
+        let entry = entries.by_token.get(token)?;
+        let age = entry.cached_at.elapsed();
+        let fresh = match &entry.validation {
+            Validation::Valid(claims) => {
+                age < self.ttl && claims.expires > ExpirationTime::from(SystemTime::now())
+            }
+            Validation::Rejected(_) => age < self.negative_ttl,
+        };
+        if !fresh {
+            entries.remove(token);
+            metrics().evictions.with_label_values(&["stale"]).inc();
+            return None;
+        }
+
+        let validation = entry.validation.clone();
+        let used = entry.used;
+        let tick = entries.next_tick();
+        entries.order.remove(&used);
+        entries.order.insert(tick, token.clone());
+        if let Some(entry) = entries.by_token.get_mut(token) {
+            entry.used = tick;
+        }
+        Some(validation)
+    }
+
+    fn insert(&self, token: &BearerToken, validation: Validation) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock();
+        entries.remove(token);
+        while entries.by_token.len() >= self.capacity {
+            let Some((_, oldest)) = entries.order.pop_first() else {
+                break;
+            };
+            entries.by_token.remove(&oldest);
+            metrics().evictions.with_label_values(&["capacity"]).inc();
+        }
+
+        let used = entries.next_tick();
+        entries.order.insert(used, token.clone());
+        entries.by_token.insert(
+            token.clone(),
+            Entry {
+                validation,
+                cached_at: Instant::now(),
+                used,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use crate::scope::Scope;
+    use std::cell::Cell;
+    use std::thread::sleep;
+
+    fn in_an_hour() -> ExpirationTime {
+        ExpirationTime::from(SystemTime::now()) + chrono::Duration::hours(1)
+    }
+
+    /// Counts the validations made through it
+    #[derive(Default)]
+    struct Validator {
+        calls: Cell<u32>,
+    }
+
+    impl Validator {
+        fn valid(
+            &self,
+            expires: ExpirationTime,
+        ) -> impl FnOnce(&BearerToken) -> Result<Claims, AuthError> + '_ {
+            move |_| {
+                self.calls.set(self.calls.get() + 1);
+                Ok(Claims {
+                    subject: "a@example.com".to_string(),
+                    user_id: Some(1),
+                    username: Some("a".to_string()),
+                    scopes: vec![Scope::User],
+                    expires,
+                })
+            }
+        }
+
+        fn failing(
+            &self,
+            error: AuthError,
+        ) -> impl FnOnce(&BearerToken) -> Result<Claims, AuthError> + '_ {
+            move |_| {
+                self.calls.set(self.calls.get() + 1);
+                Err(error)
+            }
+        }
+    }
+
+    #[test]
+    fn valid_tokens_are_cached_until_they_expire() {
+        let cache = TokenCache::new(10);
+        let token = BearerToken::from("token");
+        let validator = Validator::default();
+        let hits = metrics().lookups.with_label_values(&["hit"]);
+        let hits_before = hits.get();
+
+        let expires = in_an_hour();
+        let claims = cache.validate(&token, validator.valid(expires)).unwrap();
+        assert_eq!(claims.expires, expires);
+        assert_eq!(cache.cached(&token).unwrap().unwrap(), claims);
+        assert_eq!(
+            cache.validate(&token, validator.valid(expires)).unwrap(),
+            claims
+        );
+        assert_eq!(validator.calls.get(), 1);
+        assert!(hits.get() > hits_before);
+
+        let soon = ExpirationTime::from(SystemTime::now()) + chrono::Duration::milliseconds(20);
+        let other = BearerToken::from("expiring");
+        cache.validate(&other, validator.valid(soon)).unwrap();
+        sleep(Duration::from_millis(30));
+        let error = cache
+            .validate(&other, validator.failing(AuthError::TokenExpired(soon)))
+            .unwrap_err();
+        assert!(matches!(error, AuthError::TokenExpired(_)), "{error:?}");
+        assert_eq!(
+            validator.calls.get(),
+            3,
+            "expired tokens are validated again"
+        );
+    }
+
+    #[test]
+    fn valid_tokens_are_validated_again_after_the_ttl() {
+        let cache = TokenCache::new(10).with_ttl(Duration::from_millis(20));
+        let token = BearerToken::from("token");
+        let validator = Validator::default();
+
+        cache
+            .validate(&token, validator.valid(in_an_hour()))
+            .unwrap();
+        sleep(Duration::from_millis(30));
+        let error = cache
+            .validate(&token, validator.failing(AuthError::TokenRevoked))
+            .unwrap_err();
+        assert!(matches!(error, AuthError::TokenRevoked), "{error:?}");
+        assert_eq!(validator.calls.get(), 2);
+    }
+
+    #[test]
+    fn invalidated_tokens_are_validated_again() {
+        let cache = TokenCache::new(10);
+        let token = BearerToken::from("token");
+        let validator = Validator::default();
+
+        cache
+            .validate(&token, validator.valid(in_an_hour()))
+            .unwrap();
+        cache.invalidate(&token);
+        assert!(cache.is_empty());
+        cache
+            .validate(&token, validator.failing(AuthError::TokenRevoked))
+            .unwrap_err();
+        assert_eq!(validator.calls.get(), 2);
+    }
+
+    #[test]
+    fn rejected_tokens_are_cached_for_the_negative_ttl() {
+        let cache = TokenCache::new(10).with_negative_ttl(Duration::from_millis(20));
+        let token = BearerToken::from("token");
+        let validator = Validator::default();
+
+        cache
+            .validate(&token, validator.failing(AuthError::TokenRevoked))
+            .unwrap_err();
+        let error = cache
+            .validate(&token, validator.valid(in_an_hour()))
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::TokenRevoked);
+        assert!(matches!(error, AuthError::Rejected(_)), "{error:?}");
+        assert_eq!(validator.calls.get(), 1);
+
+        sleep(Duration::from_millis(30));
+        cache
+            .validate(&token, validator.valid(in_an_hour()))
+            .unwrap();
+        assert_eq!(validator.calls.get(), 2);
+    }
+
+    #[test]
+    fn unavailable_validations_are_not_cached() {
+        let cache = TokenCache::new(10);
+        let token = BearerToken::from("token");
+        let validator = Validator::default();
+
+        cache
+            .validate(
+                &token,
+                validator.failing(AuthError::Unavailable("down".to_string())),
+            )
+            .unwrap_err();
+        assert!(cache.is_empty());
+        cache
+            .validate(&token, validator.valid(in_an_hour()))
+            .unwrap();
+        assert_eq!(validator.calls.get(), 2);
+    }
+
+    #[test]
+    fn least_recently_used_tokens_are_evicted() {
+        let cache = TokenCache::new(2);
+        let validator = Validator::default();
+        let [a, b, c] = ["a", "b", "c"].map(BearerToken::from);
+        let evictions = metrics().evictions.with_label_values(&["capacity"]);
+        let evictions_before = evictions.get();
+
+        cache.validate(&a, validator.valid(in_an_hour())).unwrap();
+        cache.validate(&b, validator.valid(in_an_hour())).unwrap();
+        cache.validate(&a, validator.valid(in_an_hour())).unwrap();
+        cache.validate(&c, validator.valid(in_an_hour())).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(evictions.get() > evictions_before);
+
+        cache.validate(&a, validator.valid(in_an_hour())).unwrap();
+        assert_eq!(validator.calls.get(), 3, "a was used more recently than b");
+        cache.validate(&b, validator.valid(in_an_hour())).unwrap();
+        assert_eq!(validator.calls.get(), 4, "b was evicted");
+    }
+}
@@ -2,18 +2,60 @@
 
 use crate::bearer::BearerToken;
 use crate::User;
-use std::error::Error;
-use std::ops::{Deref, DerefMut};
 use async_trait::async_trait;
+use std::ops::{Deref, DerefMut};
 
-/// Defines the actions of a user service
+/// Defines the actions of a user service.
+///
+/// The [`Client`](crate::client::Client) calls the users service, and the users service can also
+/// be embedded in-process, so callers written against this trait can swap between them.
 #[async_trait]
 pub trait UserService<U: User> {
     type Authenticated: AuthenticatedUser<U>;
+    /// Why logging in failed
     type AuthError;
+    /// Why an account could not be created
+    type SignUpError;
+    /// Why logging out failed
+    type LogOutError;
+    /// Why a bearer token could not be refreshed
+    type RefreshError;
+    /// Why the user of a bearer token could not be found
+    type CurrentUserError;
+    /// Why looking up a user failed
+    type LookupError;
+
+    /// Creates an account, which can then be logged into
+    async fn sign_up(
+        &self,
+        email: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<U, Self::SignUpError>;
 
     /// Log into the user service.
-    async fn log_in(&self, user: &str, pass: &[u8]) -> Result<Self::Authenticated, Self::AuthError>;
+    async fn log_in(&self, user: &str, pass: &[u8])
+        -> Result<Self::Authenticated, Self::AuthError>;
+
+    /// Logs out of every session of the user a bearer token was issued to, revoking their tokens
+    async fn log_out(&self, bearer: &BearerToken) -> Result<(), Self::LogOutError>;
+
+    /// Issues a new bearer token to the user a bearer token was issued to. The old token stays
+    /// valid until it expires.
+    async fn refresh(
+        &self,
+        bearer: &BearerToken,
+    ) -> Result<Self::Authenticated, Self::RefreshError>;
+
+    /// Gets the user a bearer token was issued to
+    async fn current_user(&self, bearer: &BearerToken) -> Result<U, Self::CurrentUserError>;
+
+    /// Finds a user by email or username, on behalf of the user a bearer token was issued to
+    async fn lookup_user(
+        &self,
+        bearer: &BearerToken,
+        identifier: &str,
+    ) -> Result<Option<U>, Self::LookupError>;
 }
 
 /// Used to define an authenticated user interaction
@@ -21,4 +63,3 @@ pub trait AuthenticatedUser<U: User>: Deref<Target = U> + DerefMut<Target = U> {
     /// The bearer token
     fn bearer(&self) -> &BearerToken;
 }
-
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE user DROP COLUMN preferences;
+
+DROP TABLE guest;
@@ -0,0 +1,4 @@
+-- Admins, and users who have been banned from logging in
+
+ALTER TABLE user ADD COLUMN admin BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE user ADD COLUMN banned BOOLEAN NOT NULL DEFAULT FALSE;
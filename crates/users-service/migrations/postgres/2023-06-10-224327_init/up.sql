@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+CREATE TABLE "user" (
+    id BIGSERIAL PRIMARY KEY,
+    email VARCHAR(255) NOT NULL UNIQUE,
+    username VARCHAR(64) NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL
+)
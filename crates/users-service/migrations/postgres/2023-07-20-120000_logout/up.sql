@@ -0,0 +1,3 @@
+-- When every token issued to a user until then was revoked by logging out
+
+ALTER TABLE "user" ADD COLUMN tokens_revoked_at TIMESTAMP;
@@ -0,0 +1,9 @@
+-- Anonymous guest sessions, and preferences that survive upgrading a guest to a user
+
+CREATE TABLE guest (
+    id VARCHAR(36) PRIMARY KEY NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    preferences TEXT
+);
+
+ALTER TABLE user ADD COLUMN preferences TEXT;
@@ -1,122 +1,342 @@
 //! Common actions
 
-use crate::authenticator::Authenticator;
-use crate::schema::user::username;
+use crate::authenticator::{bearer, Authenticator};
+use crate::error::ServiceError;
+use crate::local::LocalUserService;
+use crate::metrics::metrics;
+use crate::preferences::Preferences;
+use crate::store::UserStore;
 use crate::user::PublicUser;
-use crate::Database;
-use actix_web::http::header::AUTHORIZATION;
-use actix_web::http::StatusCode;
-use actix_web::web::{Data, Json};
-use actix_web::{error, post, web, HttpRequest, HttpResponse, Responder};
-use base64::alphabet::URL_SAFE;
-use base64::engine::general_purpose::PAD;
-use base64::engine::GeneralPurpose;
-use base64::Engine;
-use chrono::Duration;
-use diesel::QueryDsl;
-use hmac::digest::typenum::op;
+use actix_web::web::{Data, Json, Query};
+use actix_web::{get, post, put, web, HttpRequest, HttpResponse, Responder};
+use common::secret::Secret;
 use serde::{Deserialize, Serialize};
-use std::error::Error;
 use tracing::instrument;
+use utoipa::{IntoParams, ToSchema};
 use users_api::auth::PasswordAuth;
 use users_api::error::AuthError;
+use users_api::header::Authorization;
+use users_api::scope::Scope;
+use users_api::user_service::UserService;
 use users_api::{EmailAddress, User};
 
-#[derive(Debug, Deserialize)]
-struct CreateUserBody {
-    email: String,
-    username: String,
-    password: String,
+/// The account to create
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct CreateUserBody {
+    #[schema(example = "user@example.com")]
+    pub(crate) email: String,
+    /// Can not contain `@`
+    #[schema(example = "user")]
+    pub(crate) username: String,
+    #[schema(value_type = String, format = Password)]
+    pub(crate) password: Secret<String>,
 }
 
+impl CreateUserBody {
+    /// Checks that the requested account can be created
+    pub(crate) fn validate(&self) -> Result<(), ServiceError> {
+        validate_username(&self.username)
+    }
+}
+
+/// Checks that a username can not be mistaken for an email
+pub(crate) fn validate_username(username: &str) -> Result<(), ServiceError> {
+    if username.contains('@') {
+        return Err(ServiceError::Invalid("username can not contain @".to_string()));
+    }
+    Ok(())
+}
+
+/// Creates an account
+#[utoipa::path(
+    post,
+    path = "/user/create",
+    tag = "users",
+    request_body = CreateUserBody,
+    responses(
+        (status = 200, description = "The account was created", body = UserInfo),
+        (status = 406, description = "The username contains an `@`"),
+        (status = 409, description = "The email or username is already taken"),
+    )
+)]
 #[post("user/create")]
 pub async fn create_user(
     create_user: Json<CreateUserBody>,
     password_hasher: Data<PasswordAuth>,
-    cnxn: Data<Database>,
-) -> actix_web::Result<impl Responder> {
-    if create_user.username.contains("@") {
-        return Ok(HttpResponse::NotAcceptable().body("username can not contain @"));
-    }
-
-    web::block(move || {
-        let mut conn = cnxn.get().expect("couldn't get db connection from pool");
-
-        let hashed = password_hasher
-            .hash_password(&create_user.password.as_bytes())
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-        PublicUser::create_new_user(
-            &mut conn,
+    auth: Data<Authenticator<PublicUser>>,
+    store: Data<dyn UserStore>,
+) -> actix_web::Result<Json<UserInfo>> {
+    let service = LocalUserService::new(auth, password_hasher, store);
+    let user = service
+        .sign_up(
             &create_user.email,
             &create_user.username,
-            &hashed,
+            create_user.password.expose_secret(),
         )
-        .map_err(|e| Box::new(e) as Box<dyn Error + Send>)
-    })
-    .await?
-    .map_err(error::ErrorInternalServerError)?;
+        .await?;
+    metrics().signup(false);
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(Json(UserInfo::from(&user)))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct UserInfo {
-    username: String,
-    email: EmailAddress,
+/// The account that was logged in to
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub(crate) struct UserInfo {
+    #[schema(example = "user")]
+    pub(crate) username: String,
+    #[schema(value_type = String, example = "user@example.com")]
+    pub(crate) email: EmailAddress,
 }
 
+impl From<&PublicUser> for UserInfo {
+    fn from(user: &PublicUser) -> Self {
+        Self {
+            username: user.username().to_string(),
+            email: user.email(),
+        }
+    }
+}
+
+/// Logs in with an email or username and a password, returning a bearer token
+#[utoipa::path(
+    post,
+    path = "/user/login",
+    tag = "users",
+    security(("basic" = [])),
+    responses(
+        (status = 200, description = "Logged in", body = UserInfo, headers(
+            ("authorization" = String, description = "A bearer token for the user")
+        )),
+        (status = 401, description = "The user does not exist or the password is wrong"),
+        (status = 403, description = "The user has been banned"),
+        (status = 406, description = "There are no basic credentials"),
+    )
+)]
 #[post("user/login")]
-#[instrument]
+#[instrument(skip(req, store))]
 pub async fn login_user(
     req: HttpRequest,
     password_hasher: Data<PasswordAuth>,
     auth: Data<Authenticator<PublicUser>>,
-    cnxn: Data<Database>,
+    store: Data<dyn UserStore>,
 ) -> actix_web::Result<impl Responder> {
-    let (identifier, password) = if let Some(header_value) = req.headers().get(AUTHORIZATION) {
-        if header_value.as_bytes().starts_with(b"Bearer ") {
-            todo!("bearer re-auth")
-        } else if header_value.as_bytes().starts_with(b"Basic ") {
-            let basic_auth = header_value
-                .as_bytes()
-                .strip_prefix(b"Basic ")
-                .ok_or(error::ErrorNotAcceptable("bad auth"))?;
-
-            let decoder = GeneralPurpose::new(&URL_SAFE, PAD);
-            let result = decoder
-                .decode(basic_auth)
-                .map_err(error::ErrorNotAcceptable)
-                .and_then(|vec| String::from_utf8(vec).map_err(error::ErrorNotAcceptable))?;
-
-            let (email, password) = result
-                .split_once(":")
-                .ok_or(error::ErrorNotAcceptable("bad auth"))?;
-            (email.to_string(), password.to_string())
-        } else {
-            return Err(error::ErrorNotAcceptable("invalid authorization scheme"));
-        }
-    } else {
-        return Err(error::ErrorNotAcceptable("no AUTHORIZATION header"));
-    };
+    let (identifier, password) = basic_credentials(&req).inspect_err(|_| metrics().invalid_login())?;
+
+    let service = LocalUserService::new(auth, password_hasher, store);
+    let user = service
+        .log_in(&identifier, password.expose_secret().as_bytes())
+        .await;
+    metrics().login(&user);
+    let (user, token) = user?.into_parts();
+
+    Ok(Json(UserInfo::from(&user))
+        .customize()
+        .insert_header(Authorization::Bearer(token)))
+}
+
+/// Reads the identifier and password from a `Basic` `Authorization` header
+fn basic_credentials(req: &HttpRequest) -> Result<(String, Secret<String>), ServiceError> {
+    let invalid = |message: &str| ServiceError::Invalid(message.to_string());
+    match Authorization::find(req) {
+        Ok(Some(Authorization::Basic { user, pass })) => Ok((user, pass)),
+        Ok(Some(_)) => Err(invalid("invalid authorization scheme")),
+        Ok(None) => Err(invalid("no AUTHORIZATION header")),
+        Err(e) => Err(invalid(&e.to_string())),
+    }
+}
+
+/// Gets the user in the `Authorization` header
+#[utoipa::path(
+    get,
+    path = "/user/me",
+    tag = "users",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The user", body = UserInfo),
+        (status = 401, description = "The bearer token is missing, invalid, expired or revoked"),
+        (status = 403, description = "The user has been banned, or the bearer token is a guest's"),
+    )
+)]
+#[get("user/me")]
+pub async fn get_profile(
+    req: HttpRequest,
+    password_hasher: Data<PasswordAuth>,
+    auth: Data<Authenticator<PublicUser>>,
+    store: Data<dyn UserStore>,
+) -> actix_web::Result<Json<UserInfo>> {
+    let service = LocalUserService::new(auth, password_hasher, store);
+    let user = service.current_user(&bearer(&req)?).await?;
 
-    let user = web::block(move || -> Result<PublicUser, AuthError> {
-        let mut conn = cnxn.get().expect("could not get db connection");
-        let user = PublicUser::get_user(&mut conn, &identifier)
-            .ok_or(AuthError::NoUserFound(identifier.clone()))?;
+    Ok(Json(UserInfo::from(&user)))
+}
 
-        user.verify_password(&mut conn, &password_hasher, &password)?;
+/// Logs out of every session of the user in the `Authorization` header, revoking their tokens
+#[utoipa::path(
+    post,
+    path = "/user/logout",
+    tag = "users",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Every token issued to the user until now was revoked"),
+        (status = 401, description = "The bearer token is missing, invalid, expired or revoked"),
+        (status = 403, description = "The user has been banned, or the bearer token is a guest's"),
+    )
+)]
+#[post("user/logout")]
+pub async fn log_out(
+    req: HttpRequest,
+    password_hasher: Data<PasswordAuth>,
+    auth: Data<Authenticator<PublicUser>>,
+    store: Data<dyn UserStore>,
+) -> actix_web::Result<impl Responder> {
+    let service = LocalUserService::new(auth, password_hasher, store);
+    service.log_out(&bearer(&req)?).await?;
 
-        Ok(user)
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Issues a new bearer token to the user in the `Authorization` header, leaving the old one valid
+#[utoipa::path(
+    post,
+    path = "/user/refresh",
+    tag = "users",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The user", body = UserInfo, headers(
+            ("authorization" = String, description = "A new bearer token for the user")
+        )),
+        (status = 401, description = "The bearer token is missing, invalid, expired or revoked"),
+        (status = 403, description = "The user has been banned, or the bearer token is a guest's"),
+    )
+)]
+#[post("user/refresh")]
+pub async fn refresh_token(
+    req: HttpRequest,
+    password_hasher: Data<PasswordAuth>,
+    auth: Data<Authenticator<PublicUser>>,
+    store: Data<dyn UserStore>,
+) -> actix_web::Result<impl Responder> {
+    let service = LocalUserService::new(auth, password_hasher, store);
+    let (user, token) = service.refresh(&bearer(&req)?).await?.into_parts();
+
+    Ok(Json(UserInfo::from(&user))
+        .customize()
+        .insert_header(Authorization::Bearer(token)))
+}
+
+/// The user to look up
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct LookupQuery {
+    /// The email or username of the user
+    identifier: String,
+}
+
+/// Finds a user by email or username
+#[utoipa::path(
+    get,
+    path = "/user/lookup",
+    tag = "users",
+    params(LookupQuery),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The user", body = UserInfo),
+        (status = 401, description = "The bearer token is missing, invalid, expired or revoked"),
+        (status = 403, description = "The user has been banned, or the bearer token is a guest's"),
+        (status = 404, description = "No user exists with the email or username"),
+    )
+)]
+#[get("user/lookup")]
+pub async fn lookup_user(
+    req: HttpRequest,
+    query: Query<LookupQuery>,
+    password_hasher: Data<PasswordAuth>,
+    auth: Data<Authenticator<PublicUser>>,
+    store: Data<dyn UserStore>,
+) -> actix_web::Result<Json<UserInfo>> {
+    let service = LocalUserService::new(auth, password_hasher, store);
+    let user = service
+        .lookup_user(&bearer(&req)?, &query.identifier)
+        .await?
+        .ok_or_else(|| ServiceError::UserNotFound(query.identifier.clone()))?;
+
+    Ok(Json(UserInfo::from(&user)))
+}
+
+/// Gets the preferences of the user or guest in the `Authorization` header
+#[utoipa::path(
+    get,
+    path = "/user/preferences",
+    tag = "users",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The stored preferences", body = Preferences),
+        (status = 401, description = "The bearer token is missing, invalid or expired"),
+        (status = 403, description = "The user has been banned"),
+    )
+)]
+#[get("user/preferences")]
+pub async fn get_preferences(
+    req: HttpRequest,
+    auth: Data<Authenticator<PublicUser>>,
+    store: Data<dyn UserStore>,
+) -> actix_web::Result<Json<Preferences>> {
+    let token = auth.authenticate(&req)?;
+
+    let preferences = web::block(move || -> Result<Preferences, ServiceError> {
+        let not_found = || AuthError::NoUserFound(token.subject().to_string());
+        match token.scope() {
+            Scope::User => {
+                let user = store.token_user(&token)?;
+                store.user_preferences(&user)
+            }
+            Scope::Guest => Ok(store
+                .get_guest(token.subject())?
+                .ok_or_else(not_found)?
+                .preferences()),
+        }
     })
     .await??;
 
-    let token = auth.create_token(&user, Duration::days(30))?;
+    Ok(Json(preferences))
+}
 
-    Ok(Json(UserInfo {
-        username: user.username().to_string(),
-        email: user.email(),
-    })
-    .customize()
-    .insert_header((AUTHORIZATION, token.to_string()))
+/// Replaces the preferences of the user or guest in the `Authorization` header
+#[utoipa::path(
+    put,
+    path = "/user/preferences",
+    tag = "users",
+    security(("bearer" = [])),
+    request_body = Preferences,
+    responses(
+        (status = 200, description = "The preferences were replaced"),
+        (status = 401, description = "The bearer token is missing, invalid or expired"),
+        (status = 403, description = "The user has been banned"),
     )
+)]
+#[put("user/preferences")]
+pub async fn set_preferences(
+    req: HttpRequest,
+    preferences: Json<Preferences>,
+    auth: Data<Authenticator<PublicUser>>,
+    store: Data<dyn UserStore>,
+) -> actix_web::Result<impl Responder> {
+    let token = auth.authenticate(&req)?;
+
+    web::block(move || -> Result<(), ServiceError> {
+        let not_found = || AuthError::NoUserFound(token.subject().to_string());
+        match token.scope() {
+            Scope::User => {
+                let user = store.token_user(&token)?;
+                store.set_user_preferences(&user, &preferences)
+            }
+            Scope::Guest => {
+                let guest = store.get_guest(token.subject())?.ok_or_else(not_found)?;
+                store.set_guest_preferences(&guest, &preferences)
+            }
+        }
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().finish())
 }
@@ -0,0 +1,247 @@
+//! Operator commands that work on the database directly instead of through the HTTP API
+
+use crate::authenticator::Authenticator;
+use crate::db::DbConnection;
+use crate::error::ServiceError;
+use crate::user::PublicUser;
+use clap::Subcommand;
+use common::repo::{Pageable, RepositoryError};
+use common::secret::Secret;
+use serde::Serialize;
+use std::io;
+use std::io::BufRead;
+use users_api::auth::{PasswordAuth, PasswordError};
+use users_api::bearer::BearerToken;
+use users_api::error::AuthError;
+use users_api::scope::Scope;
+use users_api::{ExpirationTime, User};
+
+/// An admin command failed
+#[derive(Debug, thiserror::Error)]
+pub enum AdminError {
+    #[error(transparent)]
+    Service(#[from] ServiceError),
+    #[error("could not read the password: {0}")]
+    Io(#[from] io::Error),
+    #[error("could not write json: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl From<RepositoryError> for AdminError {
+    fn from(value: RepositoryError) -> Self {
+        Self::Service(value.into())
+    }
+}
+
+impl From<AuthError> for AdminError {
+    fn from(value: AuthError) -> Self {
+        Self::Service(value.into())
+    }
+}
+
+impl From<PasswordError> for AdminError {
+    fn from(value: PasswordError) -> Self {
+        Self::Service(value.into())
+    }
+}
+
+/// The `user` subcommands
+#[derive(Debug, Clone, Subcommand)]
+pub enum UserCommand {
+    /// Creates a user
+    Create {
+        email: String,
+        username: String,
+        /// The password of the user. Read from stdin if not given.
+        #[clap(long)]
+        password: Option<Secret<String>>,
+        /// Makes the user an admin
+        #[clap(long)]
+        admin: bool,
+    },
+    /// Replaces the password of a user
+    ResetPassword {
+        /// The email or username of the user
+        user: String,
+        /// The new password. Read from stdin if not given.
+        #[clap(long)]
+        password: Option<Secret<String>>,
+    },
+    /// Shows a single user
+    Show {
+        /// The email or username of the user
+        user: String,
+    },
+    /// Lists users, ordered by id
+    List {
+        /// How many users to skip
+        #[clap(long, default_value_t = 0)]
+        offset: u64,
+        /// The most users to list
+        #[clap(long, default_value_t = 50)]
+        limit: u64,
+    },
+    /// Bans a user from logging in
+    Ban {
+        /// The email or username of the user
+        user: String,
+        /// Lifts the ban instead
+        #[clap(long)]
+        undo: bool,
+    },
+}
+
+/// The `token` subcommands
+#[derive(Debug, Clone, Subcommand)]
+pub enum TokenCommand {
+    /// Verifies the signature of a token and prints its claims
+    Inspect {
+        /// The token, with or without the `Bearer ` prefix
+        token: Secret<String>,
+    },
+}
+
+/// A user as it's printed
+#[derive(Debug, Serialize)]
+struct UserView {
+    id: i64,
+    email: String,
+    username: String,
+    admin: bool,
+    banned: bool,
+}
+
+impl From<&PublicUser> for UserView {
+    fn from(user: &PublicUser) -> Self {
+        Self {
+            id: user.id(),
+            email: user.email().to_string(),
+            username: user.username().to_string(),
+            admin: user.is_admin(),
+            banned: user.is_banned(),
+        }
+    }
+}
+
+impl UserView {
+    fn print(&self) {
+        let mut flags = vec![];
+        if self.admin {
+            flags.push("admin");
+        }
+        if self.banned {
+            flags.push("banned");
+        }
+        println!(
+            "{:>6}  {:<24} {:<32} {}",
+            self.id,
+            self.username,
+            self.email,
+            flags.join(",")
+        );
+    }
+}
+
+/// The claims of a token as they're printed
+#[derive(Debug, Serialize)]
+struct TokenView {
+    subject: String,
+    scope: Scope,
+    expires: ExpirationTime,
+    expired: bool,
+}
+
+impl UserCommand {
+    /// Runs the command, printing the affected users as text or json
+    pub fn run(
+        &self,
+        conn: &mut DbConnection,
+        passwords: &PasswordAuth,
+        json: bool,
+    ) -> Result<(), AdminError> {
+        let users = match self {
+            UserCommand::Create {
+                email,
+                username,
+                password,
+                admin,
+            } => {
+                let hash = passwords.hash_password(read_password(password)?.expose_secret().as_bytes())?;
+                let mut user = PublicUser::create_new_user(conn, email, username, &hash)?;
+                if *admin {
+                    user.set_admin(conn, true)?;
+                }
+                vec![user]
+            }
+            UserCommand::ResetPassword { user, password } => {
+                let user = find(conn, user)?;
+                let hash = passwords.hash_password(read_password(password)?.expose_secret().as_bytes())?;
+                user.set_password_hash(conn, &hash)?;
+                vec![user]
+            }
+            UserCommand::Show { user } => vec![find(conn, user)?],
+            UserCommand::List { offset, limit } => {
+                PublicUser::list(conn, &Pageable::offset(*offset, *limit))?.into_content()
+            }
+            UserCommand::Ban { user, undo } => {
+                let mut user = find(conn, user)?;
+                user.set_banned(conn, !undo)?;
+                vec![user]
+            }
+        };
+
+        let views: Vec<UserView> = users.iter().map(UserView::from).collect();
+        if json {
+            match (self, views.as_slice()) {
+                (UserCommand::List { .. }, _) => println!("{}", serde_json::to_string(&views)?),
+                (_, [view]) => println!("{}", serde_json::to_string(view)?),
+                _ => unreachable!("only listing returns more than one user"),
+            }
+        } else {
+            views.iter().for_each(UserView::print);
+        }
+        Ok(())
+    }
+}
+
+impl TokenCommand {
+    /// Runs the command, printing the claims as text or json
+    pub fn run(&self, auth: &Authenticator<PublicUser>, json: bool) -> Result<(), AdminError> {
+        let TokenCommand::Inspect { token } = self;
+        let token = token.expose_secret();
+        let token = token.strip_prefix("Bearer ").unwrap_or(token);
+        let claims = auth.inspect_token(&BearerToken::from(token.trim()))?;
+        let view = TokenView {
+            subject: claims.subject().to_string(),
+            scope: claims.scope(),
+            expires: claims.expiration_time(),
+            expired: claims.expiration_time() < chrono::Utc::now(),
+        };
+
+        if json {
+            println!("{}", serde_json::to_string(&view)?);
+        } else {
+            println!("subject: {}", view.subject);
+            println!("scope:   {}", view.scope);
+            let state = if view.expired { "expired" } else { "valid" };
+            println!("expires: {} ({state})", view.expires);
+        }
+        Ok(())
+    }
+}
+
+fn find(conn: &mut DbConnection, identifier: &str) -> Result<PublicUser, AdminError> {
+    Ok(PublicUser::get_user(conn, identifier)?
+        .ok_or_else(|| AuthError::NoUserFound(identifier.to_string()))?)
+}
+
+/// Uses the given password, or reads one from the first line of stdin
+fn read_password(password: &Option<Secret<String>>) -> Result<Secret<String>, AdminError> {
+    if let Some(password) = password {
+        return Ok(password.clone());
+    }
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    line.truncate(line.trim_end_matches(['\r', '\n']).len());
+    Ok(Secret::new(line))
+}
@@ -1,22 +1,25 @@
 //! Authenticates!
 
+use crate::guest::Guest;
+use crate::metrics::metrics;
+use crate::store::UserStore;
 use crate::tokens::AuthenticatedUserToken;
 use crate::user::PublicUser;
-use actix_web::http::header::Header;
-use actix_web::http::StatusCode;
 use actix_web::web::{Data, Json};
-use actix_web::{get, HttpRequest, Responder};
+use actix_web::{get, web, HttpRequest};
 use chrono::{DateTime, Duration, Utc};
 use hmac::digest::KeyInit;
 use hmac::Hmac;
-use jwt::{Error, SignWithKey, VerifyWithKey, VerifyingAlgorithm};
+use jwt::{SignWithKey, VerifyWithKey};
 use sha2::Sha384;
 use std::marker::PhantomData;
 use std::time::SystemTime;
 use tracing::{info, instrument};
 use users_api::bearer::BearerToken;
+use users_api::claims::Claims;
 use users_api::error::AuthError;
 use users_api::header::Authorization;
+use users_api::scope::Scope;
 use users_api::{ExpirationTime, User};
 
 /// Used for authenticating
@@ -42,18 +45,38 @@ impl<U: User> Authenticator<U> {
         user: &PublicUser,
         expires_in: Duration,
     ) -> Result<BearerToken, AuthError> {
-        let token = AuthenticatedUserToken::with_valid_duration(user, expires_in);
-        token
-            .sign_with_key(&self.hmac)
-            .map(|s| BearerToken::from(s))
-            .map_err(|e| e.into())
+        self.sign(AuthenticatedUserToken::with_valid_duration(user, expires_in))
     }
 
-    /// Validates the token
+    /// Creates a restricted token for a guest
+    pub fn create_guest_token(
+        &self,
+        guest: &Guest,
+        expires_in: Duration,
+    ) -> Result<BearerToken, AuthError> {
+        self.sign(AuthenticatedUserToken::guest(guest.id(), expires_in))
+    }
+
+    fn sign(&self, token: AuthenticatedUserToken) -> Result<BearerToken, AuthError> {
+        let scope = token.scope();
+        let bearer = token.sign_with_key(&self.hmac).map(BearerToken::from)?;
+        metrics().token_issued(scope);
+        Ok(bearer)
+    }
 
+    /// Validates the token
     pub fn validate_token(&self, bearer: &BearerToken) -> Result<ExpirationTime, AuthError> {
-        let tok: AuthenticatedUserToken =
-            String::from_utf8_lossy(bearer.as_ref()).verify_with_key(&self.hmac)?;
+        self.decode_token(bearer).map(|tok| tok.expiration_time())
+    }
+
+    /// Validates the bearer token found in the `Authorization` header of a request
+    pub fn authenticate(&self, req: &HttpRequest) -> Result<AuthenticatedUserToken, AuthError> {
+        self.decode_token(&bearer(req)?)
+    }
+
+    /// Verifies the signature and expiration of a token, returning its claims
+    pub fn decode_token(&self, bearer: &BearerToken) -> Result<AuthenticatedUserToken, AuthError> {
+        let tok = self.inspect_token(bearer)?;
 
         let now = DateTime::<Utc>::from(SystemTime::now());
         info!(
@@ -66,29 +89,96 @@ impl<U: User> Authenticator<U> {
             return Err(AuthError::TokenExpired(tok.expiration_time()));
         }
 
-        Ok(tok.expiration_time())
+        Ok(tok)
+    }
+
+    /// Verifies the signature of a token, returning its claims even if it has expired
+    pub fn inspect_token(&self, bearer: &BearerToken) -> Result<AuthenticatedUserToken, AuthError> {
+        Ok(String::from_utf8_lossy(bearer.expose_secret()).verify_with_key(&self.hmac)?)
     }
 }
 
+/// Reads the bearer token in the `Authorization` header of a request
+pub fn bearer(req: &HttpRequest) -> Result<BearerToken, AuthError> {
+    match Authorization::find(req) {
+        Ok(Some(Authorization::Bearer(bearer))) => Ok(bearer),
+        _ => Err(AuthError::TokenParseError),
+    }
+}
+
+/// Checks the bearer token in the `Authorization` header, returning when it expires
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "tokens",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The token is valid", body = ExpirationTime),
+        (status = 401, description = "The bearer token is missing, invalid, expired or revoked, or its guest was upgraded"),
+        (status = 403, description = "The user has been banned"),
+    )
+)]
 #[get("/")]
-#[instrument]
+#[instrument(skip(store, req))]
 pub async fn validate_token(
     auth: Data<Authenticator<PublicUser>>,
+    store: Data<dyn UserStore>,
     req: HttpRequest,
-) -> Result<Json<ExpirationTime>, AuthError> {
-    let Ok(auth_header) = Authorization::parse(&req) else {
-        return Err(AuthError::TokenParseError)
-    };
+) -> actix_web::Result<Json<ExpirationTime>> {
+    let token = auth.authenticate(&req)?;
+    let expires = token.expiration_time();
+    match token.scope() {
+        Scope::User => {
+            web::block(move || store.token_user(&token)).await??;
+        }
+        Scope::Guest => {
+            web::block(move || store.token_guest(&token)).await??;
+        }
+    }
+    Ok(Json(expires))
+}
 
-    let bearer = auth_header.bearer();
-    auth.validate_token(bearer).map(Json)
+/// Checks the bearer token in the `Authorization` header, returning who it was issued to and what
+/// it grants
+#[utoipa::path(
+    get,
+    path = "/claims",
+    tag = "tokens",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The token is valid", body = Claims),
+        (status = 401, description = "The bearer token is missing, invalid, expired or revoked, or its guest was upgraded"),
+        (status = 403, description = "The user has been banned"),
+    )
+)]
+#[get("/claims")]
+#[instrument(skip(store, req))]
+pub async fn get_claims(
+    auth: Data<Authenticator<PublicUser>>,
+    store: Data<dyn UserStore>,
+    req: HttpRequest,
+) -> actix_web::Result<Json<Claims>> {
+    let token = auth.authenticate(&req)?;
+    let mut claims = token.claims();
+    match token.scope() {
+        Scope::User => {
+            let user = web::block(move || store.token_user(&token)).await??;
+            claims.user_id = Some(user.id());
+            claims.username = Some(user.username().to_string());
+        }
+        Scope::Guest => {
+            web::block(move || store.token_guest(&token)).await??;
+        }
+    }
+    Ok(Json(claims))
 }
 
 #[cfg(test)]
 mod tests {
     use crate::authenticator::Authenticator;
     use chrono::Duration;
-    use users_api::{EmailAddress, User};
+    use users_api::EmailAddress;
+    use users_api::auth::{AuthService, LocalAuthService};
     use crate::user::PublicUser;
 
     #[test]
@@ -99,4 +189,20 @@ mod tests {
         let bearer = auth.create_token(&PublicUser::new(EmailAddress::new_unchecked("test"), "test".to_string()), Duration::days(30)).unwrap();
         auth.validate_token(&bearer).expect("couldn't verify");
     }
+
+    #[test]
+    fn tokens_validate_locally() {
+        let auth = Authenticator::<PublicUser>::new(b"password");
+        let user = PublicUser::new(EmailAddress::new_unchecked("test"), "test".to_string());
+        let bearer = auth.create_token(&user, Duration::days(30)).unwrap();
+
+        let expires = LocalAuthService::new(b"password")
+            .validate_token(&bearer)
+            .unwrap();
+        assert_eq!(expires, auth.validate_token(&bearer).unwrap());
+        let claims = LocalAuthService::new(b"password").claims(&bearer).unwrap();
+        assert_eq!(claims.username.as_deref(), Some("test"));
+        assert_eq!(claims.user_id, Some(0));
+        assert!(LocalAuthService::new(b"other").validate_token(&bearer).is_err());
+    }
 }
@@ -0,0 +1,86 @@
+//! Configuration of the users service
+
+use common::config::{
+    Config, DatabaseConfig, LoggingConfig, ServerConfig, ShutdownConfig, TlsConfig,
+};
+use common::secret::Secret;
+use serde::{Deserialize, Serialize};
+
+/// Every setting of the users service
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsersServiceConfig {
+    pub logging: LoggingConfig,
+    pub server: ServerConfig,
+    pub tls: TlsConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub shutdown: ShutdownConfig,
+}
+
+/// How bearer tokens are issued
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// The secret bearer tokens are signed with. It has no default, so every deployment picks
+    /// its own.
+    #[serde(serialize_with = "common::secret::serialize_exposed")]
+    pub token_secret: Secret<String>,
+}
+
+impl Config for UsersServiceConfig {
+    const ENV_PREFIX: &'static str = "USERS_SERVICE_";
+    const SECRETS: &'static [&'static str] =
+        &["database.url", "database.read_url", "auth.token_secret"];
+    const ENV_ALIASES: &'static [(&'static str, &'static str)] = &[
+        ("DATABASE_URL", "database.url"),
+        ("DATABASE_READ_URL", "database.read_url"),
+        ("RUST_LOG", "logging.filter"),
+        ("OTEL_EXPORTER_OTLP_ENDPOINT", "logging.otlp_endpoint"),
+    ];
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = self.logging.validate();
+        problems.extend(self.server.validate());
+        problems.extend(self.tls.validate());
+        problems.extend(self.database.validate());
+        if self.auth.token_secret.expose_secret().is_empty() {
+            problems.push(
+                "auth.token_secret must be set, such as with USERS_SERVICE_AUTH__TOKEN_SECRET"
+                    .to_string(),
+            );
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::config::to_redacted_toml;
+
+    #[test]
+    fn token_secret_is_redacted() {
+        let mut config = UsersServiceConfig::default();
+        config.auth.token_secret = Secret::new("hunter2".to_string());
+        assert!(!format!("{config:?}").contains("hunter2"));
+        assert!(!to_redacted_toml(&config).unwrap().contains("hunter2"));
+        // the defaults are merged with the other sources through serde
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!(value["auth"]["token_secret"], "hunter2");
+    }
+
+    #[test]
+    fn token_secret_has_no_default() {
+        let mut config = UsersServiceConfig::default();
+        let missing = |config: &UsersServiceConfig| {
+            config
+                .validate()
+                .iter()
+                .any(|problem| problem.starts_with("auth.token_secret"))
+        };
+        assert!(missing(&config));
+        config.auth.token_secret = Secret::new("hunter2".to_string());
+        assert!(!missing(&config));
+    }
+}
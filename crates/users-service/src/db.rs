@@ -0,0 +1,233 @@
+//! Database connections. The backend is picked with the `mysql`, `postgres` or `sqlite` feature.
+
+use crate::migrate;
+use common::config::DatabaseConfig;
+use diesel::r2d2::ConnectionManager;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
+use parking_lot::RwLock;
+use r2d2::{Pool, PooledConnection};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(any(
+    all(feature = "mysql", feature = "postgres"),
+    all(feature = "mysql", feature = "sqlite"),
+    all(feature = "postgres", feature = "sqlite"),
+))]
+compile_error!("only one of the `mysql`, `postgres` and `sqlite` features can be enabled");
+
+#[cfg(not(any(feature = "mysql", feature = "postgres", feature = "sqlite")))]
+compile_error!("one of the `mysql`, `postgres` or `sqlite` features must be enabled");
+
+/// The connection type of the selected backend
+#[cfg(feature = "mysql")]
+pub type DbConnection = diesel::MysqlConnection;
+/// The connection type of the selected backend
+#[cfg(feature = "postgres")]
+pub type DbConnection = diesel::PgConnection;
+/// The connection type of the selected backend
+#[cfg(feature = "sqlite")]
+pub type DbConnection = diesel::SqliteConnection;
+
+/// The migrations of the selected backend
+#[cfg(feature = "mysql")]
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/mysql");
+/// The migrations of the selected backend
+#[cfg(feature = "postgres")]
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/postgres");
+/// The migrations of the selected backend
+#[cfg(feature = "sqlite")]
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/sqlite");
+
+/// A pool of database connections
+pub type Database = Pool<ConnectionManager<DbConnection>>;
+/// A connection taken from the [`Database`] pool
+pub type PooledDbConnection = PooledConnection<ConnectionManager<DbConnection>>;
+
+/// How long reads of a subject stay on the primary after it was written, so they see that write
+/// even if the replica lags behind
+pub const READ_YOUR_WRITES_WINDOW: Duration = Duration::from_secs(5);
+
+/// How long a read waits for a replica connection before going to the primary, kept short so
+/// reads don't stall while the replica is down
+pub const REPLICA_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Why the database couldn't be set up
+#[derive(Debug, thiserror::Error)]
+pub enum ConnectError {
+    #[error("database.url or DATABASE_URL must be set")]
+    MissingUrl,
+    #[error("could not connect to the database: {0}")]
+    Pool(#[from] r2d2::Error),
+}
+
+/// Connects to the database at `database.url`. Migrations are left to [`migrate`](crate::migrate).
+pub fn establish_connection(config: &DatabaseConfig) -> Result<Database, ConnectError> {
+    let url = config.url.as_deref().ok_or(ConnectError::MissingUrl)?;
+    try_connect(url, config)
+}
+
+/// Connects to the primary database at `database.url`, and to the read replica at
+/// `database.read_url` if it's set
+pub fn establish_router(config: &DatabaseConfig) -> Result<DatabaseRouter, ConnectError> {
+    let router = DatabaseRouter::new(establish_connection(config)?);
+    Ok(match &config.read_url {
+        Some(url) => router.with_replica(try_connect(url, config)?),
+        None => router,
+    })
+}
+
+/// Connects to the database at the given url with the default pool settings, running any
+/// pending migrations.
+///
+/// For sqlite, the url is the path of the database file.
+///
+/// # Panics
+/// Panics if the database can't be connected to or migrated.
+pub fn connect(url: &str) -> Database {
+    let pool = try_connect(url, &DatabaseConfig::default()).expect("could not set up the database");
+    {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+        migrate::up(&mut conn).expect("could not run migrations");
+    }
+    pool
+}
+
+/// Connects to the database at the given url.
+///
+/// Connecting is retried with the backoff of `config`, so the database can come up after the
+/// service does.
+pub fn try_connect(url: &str, config: &DatabaseConfig) -> Result<Database, ConnectError> {
+    let pool = config.backoff().retry("connecting to the database", || {
+        let builder = config.pool_builder();
+        #[cfg(feature = "sqlite")]
+        let builder = builder.connection_customizer(Box::new(sqlite::SqliteCustomizer));
+        builder.build(ConnectionManager::<DbConnection>::new(url))
+    })?;
+    Ok(pool)
+}
+
+/// Sends writes to the primary database and reads to an optional read replica.
+///
+/// Reads and writes name the subject whose records they touch, such as the email or username of
+/// a user or the id of a guest. Reads of a subject written within [`READ_YOUR_WRITES_WINDOW`] go
+/// to the primary instead, so a writer sees its own writes without moving everyone else's reads
+/// off the replica. Reads also go to the primary when no replica connection can be had within
+/// [`REPLICA_TIMEOUT`].
+#[derive(Debug, Clone)]
+pub struct DatabaseRouter {
+    primary: Database,
+    replica: Option<Database>,
+    window: Duration,
+    replica_timeout: Duration,
+    /// When each subject was last written, kept for the length of the window
+    recent_writes: Arc<RwLock<HashMap<String, Instant>>>,
+}
+
+impl DatabaseRouter {
+    /// Creates a router that sends everything to the primary
+    pub fn new(primary: Database) -> Self {
+        Self {
+            primary,
+            replica: None,
+            window: READ_YOUR_WRITES_WINDOW,
+            replica_timeout: REPLICA_TIMEOUT,
+            recent_writes: Arc::default(),
+        }
+    }
+
+    /// Sends reads to the given replica
+    pub fn with_replica(mut self, replica: Database) -> Self {
+        self.replica = Some(replica);
+        self
+    }
+
+    /// Changes how long reads of a subject stay on the primary after it was written
+    pub fn with_read_your_writes_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Changes how long a read waits for a replica connection before going to the primary
+    pub fn with_replica_timeout(mut self, timeout: Duration) -> Self {
+        self.replica_timeout = timeout;
+        self
+    }
+
+    /// The primary database
+    pub fn primary(&self) -> &Database {
+        &self.primary
+    }
+
+    /// The read replica, if there is one
+    pub fn replica(&self) -> Option<&Database> {
+        self.replica.as_ref()
+    }
+
+    /// Gets a connection to the primary for writing.
+    ///
+    /// The subjects written should be recorded with [`wrote`](Self::wrote) once the write is done.
+    pub fn write(&self) -> Result<PooledDbConnection, r2d2::Error> {
+        self.primary.get()
+    }
+
+    /// Records that the records of a subject were just written, keeping its reads on the primary
+    /// for the length of the window
+    pub fn wrote(&self, subject: &str) {
+        if self.replica.is_none() {
+            return;
+        }
+        let now = Instant::now();
+        let mut recent_writes = self.recent_writes.write();
+        recent_writes.retain(|_, at| now.duration_since(*at) < self.window);
+        recent_writes.insert(subject.to_string(), now);
+    }
+
+    /// Gets a connection for reading the records of a subject, from the replica unless the
+    /// subject was written recently
+    pub fn read(&self, subject: &str) -> Result<PooledDbConnection, r2d2::Error> {
+        let Some(replica) = &self.replica else {
+            return self.primary.get();
+        };
+        let recently_written = self
+            .recent_writes
+            .read()
+            .get(subject)
+            .is_some_and(|at| at.elapsed() < self.window);
+        if recently_written {
+            return self.primary.get();
+        }
+        match replica
+            .try_get()
+            .or_else(|| replica.get_timeout(self.replica_timeout).ok())
+        {
+            Some(conn) => Ok(conn),
+            None => self.primary.get(),
+        }
+    }
+}
+
+impl From<Database> for DatabaseRouter {
+    fn from(primary: Database) -> Self {
+        Self::new(primary)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use diesel::connection::SimpleConnection;
+    use diesel::r2d2::{CustomizeConnection, Error};
+    use diesel::SqliteConnection;
+
+    /// Lets pooled sqlite connections wait on each other instead of failing with `SQLITE_BUSY`
+    #[derive(Debug)]
+    pub struct SqliteCustomizer;
+
+    impl CustomizeConnection<SqliteConnection, Error> for SqliteCustomizer {
+        fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), Error> {
+            conn.batch_execute("PRAGMA journal_mode = WAL; PRAGMA busy_timeout = 5000;")
+                .map_err(Error::QueryError)
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Errors returned by the users service
+
+use actix_web::error::{BlockingError, JsonPayloadError};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use common::repo::RepositoryError;
+use users_api::auth::PasswordError;
+use users_api::error::{AuthError, ErrorBody, ErrorCode};
+
+/// An error occurred while handling a request
+#[derive(Debug, thiserror::Error)]
+pub enum ServiceError {
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    #[error("a user already exists with {0:?}")]
+    UserExists(String),
+    #[error("no user exists with {0:?}")]
+    UserNotFound(String),
+    #[error("{0:?} has been banned")]
+    Banned(String),
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+    #[error(transparent)]
+    Blocking(#[from] BlockingError),
+}
+
+impl From<diesel::result::Error> for ServiceError {
+    fn from(value: diesel::result::Error) -> Self {
+        Self::Repository(value.into())
+    }
+}
+
+impl From<PasswordError> for ServiceError {
+    fn from(value: PasswordError) -> Self {
+        Self::Auth(value.into())
+    }
+}
+
+impl ResponseError for ServiceError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ServiceError::Auth(e) => e.status_code(),
+            ServiceError::UserExists(_) => StatusCode::CONFLICT,
+            ServiceError::UserNotFound(_) => StatusCode::NOT_FOUND,
+            ServiceError::Banned(_) => StatusCode::FORBIDDEN,
+            ServiceError::Invalid(_) => StatusCode::NOT_ACCEPTABLE,
+            ServiceError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ServiceError::Repository(_) | ServiceError::Blocking(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let body = match self {
+            ServiceError::Auth(e) => return e.error_response(),
+            ServiceError::UserExists(_) => ErrorBody::new(ErrorCode::UserExists, self),
+            ServiceError::UserNotFound(_) => ErrorBody::new(ErrorCode::UserNotFound, self),
+            ServiceError::Banned(_) => ErrorBody::new(ErrorCode::Banned, self),
+            ServiceError::Invalid(_) | ServiceError::BadRequest(_) => {
+                ErrorBody::new(ErrorCode::InvalidRequest, self)
+            }
+            ServiceError::Repository(_) | ServiceError::Blocking(_) => {
+                ErrorBody::new(ErrorCode::Internal, "internal error")
+            }
+        };
+        body.into_response(self.status_code())
+    }
+}
+
+/// Reports json bodies that could not be read as a json error
+pub(crate) fn json_error(error: JsonPayloadError, _: &HttpRequest) -> actix_web::Error {
+    ServiceError::BadRequest(error.to_string()).into()
+}
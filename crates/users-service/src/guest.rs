@@ -0,0 +1,335 @@
+//! Anonymous guest sessions
+
+use crate::actions::{CreateUserBody, UserInfo};
+use crate::authenticator::Authenticator;
+use crate::db::{DbConnection, PooledDbConnection};
+use crate::error::ServiceError;
+use crate::metrics::metrics;
+use crate::preferences::Preferences;
+use crate::schema::guest::dsl::guest;
+use crate::store::UserStore;
+use crate::user::PublicUser;
+use actix_web::web::{Data, Json, Query};
+use actix_web::{post, web, HttpRequest, Responder};
+use chrono::{Duration, NaiveDateTime, Utc};
+use common::repo::{PooledRepository, Repository, RepositoryResult};
+use diesel::delete;
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use std::ops::DerefMut;
+use users_api::auth::PasswordAuth;
+use users_api::header::Authorization;
+use users_api::scope::Scope;
+use users_api::{ExpirationTime, User};
+use utoipa::{IntoParams, ToSchema};
+
+/// How many seconds a guest session lasts if not specified
+pub const DEFAULT_GUEST_EXPIRATION_SECS: i64 = 60 * 60;
+/// The most seconds a guest session may last
+pub const MAX_GUEST_EXPIRATION_SECS: i64 = 24 * 60 * 60;
+
+/// An anonymous visitor
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable, Insertable, AsChangeset)]
+#[diesel(table_name = crate::schema::guest, treat_none_as_null = true)]
+pub struct Guest {
+    id: String,
+    expires_at: NaiveDateTime,
+    preferences: Option<String>,
+}
+
+common::diesel_repository! {
+    /// Stores guests
+    pub struct GuestRepository<DbConnection>: Guest, String => crate::schema::guest,
+        sort: [id, expires_at];
+}
+
+/// Stores guests from async code, using connections from the [`Database`](crate::db::Database) pool
+pub type AsyncGuestRepository =
+    PooledRepository<ConnectionManager<DbConnection>, GuestRepository<PooledDbConnection>>;
+
+impl<C: DerefMut<Target = DbConnection>> GuestRepository<C> {
+    /// Removes every guest that has expired
+    pub fn delete_expired(&mut self) -> RepositoryResult<usize> {
+        use crate::schema::guest::dsl;
+        let deleted = delete(guest.filter(dsl::expires_at.lt(Utc::now().naive_utc())))
+            .execute(self.connection())?;
+        Ok(deleted)
+    }
+}
+
+impl Guest {
+    /// Creates a new guest that expires after the given duration. Guests that have
+    /// already expired are cleaned up at the same time.
+    pub fn create_new_guest(
+        conn: &mut DbConnection,
+        expires_in: Duration,
+    ) -> RepositoryResult<Guest> {
+        let mut guests = GuestRepository::new(conn);
+        guests.delete_expired()?;
+
+        let created = Guest::new(
+            uuid::Uuid::new_v4().to_string(),
+            Utc::now().naive_utc() + expires_in,
+        );
+        guests.save(created)
+    }
+
+    /// Gets a guest by its id, if it still exists and has not expired
+    pub fn get_guest(conn: &mut DbConnection, id: &str) -> RepositoryResult<Option<Guest>> {
+        let found = GuestRepository::new(conn)
+            .find_by_id(id.to_string())?
+            .filter(|found| found.expires_at >= Utc::now().naive_utc());
+        Ok(found)
+    }
+
+    /// Replaces the preferences of this guest
+    pub fn set_preferences(
+        &self,
+        conn: &mut DbConnection,
+        preferences: &Preferences,
+    ) -> RepositoryResult<()> {
+        GuestRepository::new(conn).save(self.with_preferences(preferences))?;
+        Ok(())
+    }
+
+    /// Turns this guest into a full user, carrying over everything the guest has stored.
+    pub fn upgrade(
+        self,
+        conn: &mut DbConnection,
+        email: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<PublicUser, ServiceError> {
+        conn.transaction(|conn| {
+            let user = PublicUser::create_new_user(conn, email, username, password)?;
+            user.set_preferences(conn, &self.preferences())?;
+            GuestRepository::new(conn).delete(self)?;
+            Ok(user)
+        })
+    }
+
+    /// Creates a guest without storing it
+    pub(crate) fn new(id: String, expires_at: NaiveDateTime) -> Self {
+        Self {
+            id,
+            expires_at,
+            preferences: None,
+        }
+    }
+
+    /// A copy of this guest with different preferences
+    pub(crate) fn with_preferences(&self, preferences: &Preferences) -> Self {
+        Self {
+            preferences: Some(preferences.to_column()),
+            ..self.clone()
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn expiration_time(&self) -> ExpirationTime {
+        self.expires_at.and_utc()
+    }
+
+    pub fn preferences(&self) -> Preferences {
+        Preferences::from_column(self.preferences.as_deref())
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GuestOptions {
+    /// How many seconds the guest session should last
+    expires_after: Option<u64>,
+}
+
+impl GuestOptions {
+    /// The requested session length, capped at [`MAX_GUEST_EXPIRATION_SECS`]
+    fn expires_after(&self) -> Duration {
+        let secs = self
+            .expires_after
+            .map(|secs| secs.min(MAX_GUEST_EXPIRATION_SECS as u64) as i64)
+            .unwrap_or(DEFAULT_GUEST_EXPIRATION_SECS);
+        Duration::seconds(secs)
+    }
+}
+
+/// The guest session that was started
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub(crate) struct GuestInfo {
+    id: String,
+    #[schema(value_type = String, format = DateTime)]
+    expires: ExpirationTime,
+}
+
+/// Starts a new guest session, returning a guest bearer token
+#[utoipa::path(
+    post,
+    path = "/user/guest",
+    tag = "guests",
+    params(GuestOptions),
+    responses(
+        (status = 200, description = "The guest session was started", body = GuestInfo, headers(
+            ("authorization" = String, description = "A bearer token for the guest")
+        )),
+    )
+)]
+#[post("user/guest")]
+pub async fn create_guest(
+    options: Query<GuestOptions>,
+    auth: Data<Authenticator<PublicUser>>,
+    store: Data<dyn UserStore>,
+) -> actix_web::Result<impl Responder> {
+    let expires_after = options.expires_after();
+    let created = web::block(move || store.create_guest(expires_after)).await??;
+
+    let token = auth.create_guest_token(&created, expires_after)?;
+
+    Ok(Json(GuestInfo {
+        id: created.id().to_string(),
+        expires: created.expiration_time(),
+    })
+    .customize()
+    .insert_header(Authorization::Bearer(token)))
+}
+
+/// Upgrades the guest session in the `Authorization` header to a full account
+#[utoipa::path(
+    post,
+    path = "/user/upgrade",
+    tag = "guests",
+    security(("bearer" = [])),
+    request_body = CreateUserBody,
+    responses(
+        (status = 200, description = "The account was created", body = UserInfo, headers(
+            ("authorization" = String, description = "A bearer token for the new user")
+        )),
+        (status = 401, description = "The bearer token is missing, invalid or expired, or its guest was upgraded"),
+        (status = 403, description = "The bearer token is not a guest's"),
+        (status = 406, description = "The username contains an `@`"),
+        (status = 409, description = "The email or username is already taken"),
+    )
+)]
+#[post("user/upgrade")]
+pub async fn upgrade_guest(
+    req: HttpRequest,
+    create_user: Json<CreateUserBody>,
+    password_hasher: Data<PasswordAuth>,
+    auth: Data<Authenticator<PublicUser>>,
+    store: Data<dyn UserStore>,
+) -> actix_web::Result<impl Responder> {
+    let token = auth.authenticate(&req)?;
+    token.require_scope(Scope::Guest)?;
+    create_user.validate()?;
+
+    let user = web::block(move || -> Result<PublicUser, ServiceError> {
+        let existing = store.token_guest(&token)?;
+
+        let hashed = password_hasher.hash_password(create_user.password.expose_secret().as_bytes())?;
+        store.upgrade_guest(existing, &create_user.email, &create_user.username, &hashed)
+    })
+    .await??;
+    metrics().signup(true);
+
+    let token = auth.create_token(&user, Duration::days(30))?;
+
+    Ok(Json(UserInfo {
+        username: user.username().to_string(),
+        email: user.email(),
+    })
+    .customize()
+    .insert_header(Authorization::Bearer(token)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authenticator::Authenticator;
+    use crate::store::memory::InMemoryUserStore;
+    use crate::store::store_data;
+    use crate::user::PublicUser;
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::http::StatusCode;
+    use actix_web::web::Data;
+    use actix_web::{test, App};
+    use serde_json::json;
+    use users_api::auth::PasswordAuth;
+
+    #[actix_web::test]
+    async fn guests_keep_their_preferences_when_upgraded() {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(Authenticator::<PublicUser>::new(b"password")))
+                .app_data(Data::new(PasswordAuth::new()))
+                .app_data(store_data(InMemoryUserStore::new()))
+                .configure(crate::configure),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/user/guest?expires_after=60")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success(), "{:?}", resp.status());
+        let guest = resp.headers().get(AUTHORIZATION).unwrap().clone();
+        let info: serde_json::Value = test::read_body_json(resp).await;
+        assert!(info["id"].is_string(), "{info}");
+
+        let req = test::TestRequest::put()
+            .uri("/user/preferences")
+            .insert_header((AUTHORIZATION, guest.clone()))
+            .set_json(json!({ "theme": "dark" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success(), "{:?}", resp.status());
+
+        // guests have no account, so a guest token is refused where a user's is needed
+        let req = test::TestRequest::get()
+            .uri("/user/me")
+            .insert_header((AUTHORIZATION, guest.clone()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "missing_scope", "{body}");
+
+        // only guests can be upgraded, so the upgraded account can't be upgraded again
+        let upgrade = |bearer| {
+            test::TestRequest::post()
+                .uri("/user/upgrade")
+                .insert_header((AUTHORIZATION, bearer))
+                .set_json(json!({
+                    "email": "guest@example.com",
+                    "username": "guest",
+                    "password": "password"
+                }))
+                .to_request()
+        };
+        let resp = test::call_service(&app, upgrade(guest.clone())).await;
+        assert!(resp.status().is_success(), "{:?}", resp.status());
+        let user = resp.headers().get(AUTHORIZATION).unwrap().clone();
+        let resp = test::call_service(&app, upgrade(user.clone())).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let req = test::TestRequest::get()
+            .uri("/user/preferences")
+            .insert_header((AUTHORIZATION, user))
+            .to_request();
+        let preferences: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(preferences, json!({ "theme": "dark" }));
+
+        // the guest no longer exists, so its token is rejected
+        for uri in ["/", "/claims", "/user/preferences"] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .insert_header((AUTHORIZATION, guest.clone()))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{uri}");
+        }
+        let resp = test::call_service(&app, upgrade(guest)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
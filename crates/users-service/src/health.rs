@@ -0,0 +1,51 @@
+//! Readiness checks of the users service
+
+use crate::db::{Database, DatabaseRouter, MIGRATIONS};
+use common::health::HealthCheck;
+use diesel_migrations::MigrationHarness;
+use std::time::Duration;
+
+/// How long a check waits for a database connection
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Checks that connections can be made to the primary database and the read replica
+#[derive(Debug)]
+pub struct DatabaseCheck(pub DatabaseRouter);
+
+impl HealthCheck for DatabaseCheck {
+    fn name(&self) -> &str {
+        "database"
+    }
+
+    fn check(&self) -> Result<(), String> {
+        self.0
+            .primary()
+            .get_timeout(CHECK_TIMEOUT)
+            .map_err(|e| format!("primary: {e}"))?;
+        if let Some(replica) = self.0.replica() {
+            replica
+                .get_timeout(CHECK_TIMEOUT)
+                .map_err(|e| format!("replica: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the database has no pending migrations
+#[derive(Debug)]
+pub struct MigrationCheck(pub Database);
+
+impl HealthCheck for MigrationCheck {
+    fn name(&self) -> &str {
+        "migrations"
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let mut conn = self.0.get_timeout(CHECK_TIMEOUT).map_err(|e| e.to_string())?;
+        match conn.has_pending_migration(MIGRATIONS) {
+            Ok(false) => Ok(()),
+            Ok(true) => Err("there are pending migrations".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! The users service, which manages accounts, guests and their bearer tokens
+
+use actix_web::web::{JsonConfig, ServiceConfig};
+
+pub mod actions;
+pub mod admin;
+pub mod authenticator;
+pub mod config;
+pub mod db;
+pub mod error;
+pub mod guest;
+pub mod health;
+pub mod local;
+pub mod metrics;
+pub mod migrate;
+pub mod openapi;
+pub mod preferences;
+pub mod schema;
+pub mod store;
+#[cfg(all(feature = "sqlite", any(test, feature = "test-util")))]
+pub mod test_util;
+pub mod tokens;
+pub mod user;
+
+/// Registers every route of the users service.
+///
+/// Expects an [`Authenticator`](authenticator::Authenticator),
+/// [`PasswordAuth`](users_api::auth::PasswordAuth) and [`UserStore`](store::UserStore) as app data.
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.app_data(JsonConfig::default().error_handler(error::json_error))
+        .service(authenticator::validate_token)
+        .service(authenticator::get_claims)
+        .service(actions::create_user)
+        .service(actions::login_user)
+        .service(actions::get_profile)
+        .service(actions::log_out)
+        .service(actions::refresh_token)
+        .service(actions::lookup_user)
+        .service(guest::create_guest)
+        .service(guest::upgrade_guest)
+        .service(actions::get_preferences)
+        .service(actions::set_preferences);
+}
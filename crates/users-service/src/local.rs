@@ -0,0 +1,237 @@
+//! The users service embedded in-process
+//!
+//! [`LocalUserService`] implements [`UserService`] over the same store and authenticator the
+//! handlers use, so a process holding the database can skip the network.
+
+use crate::actions::validate_username;
+use crate::authenticator::Authenticator;
+use crate::error::ServiceError;
+use crate::store::UserStore;
+use crate::user::PublicUser;
+use actix_web::web::{self, Data};
+use async_trait::async_trait;
+use chrono::Duration;
+use common::secret::Secret;
+use std::ops::{Deref, DerefMut};
+use users_api::auth::{PasswordAuth, PasswordError};
+use users_api::bearer::BearerToken;
+use users_api::error::AuthError;
+use users_api::scope::Scope;
+use users_api::user_service::{AuthenticatedUser, UserService};
+use users_api::User;
+
+/// How many days the tokens issued to users are valid
+const TOKEN_DAYS: i64 = 30;
+
+/// A [`UserService`] backed directly by a [`UserStore`]
+#[derive(Debug, Clone)]
+pub struct LocalUserService {
+    auth: Data<Authenticator<PublicUser>>,
+    passwords: Data<PasswordAuth>,
+    store: Data<dyn UserStore>,
+}
+
+impl LocalUserService {
+    pub fn new(
+        auth: Data<Authenticator<PublicUser>>,
+        passwords: Data<PasswordAuth>,
+        store: Data<dyn UserStore>,
+    ) -> Self {
+        Self {
+            auth,
+            passwords,
+            store,
+        }
+    }
+
+    /// The user a valid, unrevoked user token was issued to
+    async fn token_user(&self, bearer: &BearerToken) -> Result<PublicUser, ServiceError> {
+        let token = self.auth.decode_token(bearer)?;
+        token.require_scope(Scope::User)?;
+        let store = self.store.clone();
+        web::block(move || store.token_user(&token)).await?
+    }
+
+    /// Issues a token to a user, unless they have been banned
+    fn authenticate(&self, user: PublicUser) -> Result<AuthenticatedPublicUser, ServiceError> {
+        if user.is_banned() {
+            return Err(ServiceError::Banned(user.username().to_string()));
+        }
+        Ok(AuthenticatedPublicUser {
+            bearer: self.auth.create_token(&user, Duration::days(TOKEN_DAYS))?,
+            user,
+        })
+    }
+}
+
+#[async_trait]
+impl UserService<PublicUser> for LocalUserService {
+    type Authenticated = AuthenticatedPublicUser;
+    type AuthError = ServiceError;
+    type SignUpError = ServiceError;
+    type LogOutError = ServiceError;
+    type RefreshError = ServiceError;
+    type CurrentUserError = ServiceError;
+    type LookupError = ServiceError;
+
+    async fn sign_up(
+        &self,
+        email: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<PublicUser, Self::SignUpError> {
+        validate_username(username)?;
+
+        let (email, username) = (email.to_string(), username.to_string());
+        let password = Secret::new(password.to_string());
+        let (passwords, store) = (self.passwords.clone(), self.store.clone());
+        web::block(move || {
+            let hashed = passwords.hash_password(password.expose_secret().as_bytes())?;
+            store.create_user(&email, &username, &hashed)
+        })
+        .await?
+    }
+
+    async fn log_in(
+        &self,
+        user: &str,
+        pass: &[u8],
+    ) -> Result<Self::Authenticated, Self::AuthError> {
+        let password = std::str::from_utf8(pass)
+            .map(|pass| Secret::new(pass.to_string()))
+            .map_err(|_| PasswordError::IncorrectPassword)?;
+
+        let identifier = user.to_string();
+        let (passwords, store) = (self.passwords.clone(), self.store.clone());
+        let user = web::block(move || -> Result<PublicUser, ServiceError> {
+            let user = store
+                .get_user(&identifier)?
+                .ok_or(AuthError::NoUserFound(identifier.clone()))?;
+            store.verify_password(&user, &passwords, password.expose_secret())?;
+            Ok(user)
+        })
+        .await??;
+
+        self.authenticate(user)
+    }
+
+    async fn log_out(&self, bearer: &BearerToken) -> Result<(), Self::LogOutError> {
+        let user = self.token_user(bearer).await?;
+        let store = self.store.clone();
+        web::block(move || store.revoke_tokens(&user)).await?
+    }
+
+    async fn refresh(
+        &self,
+        bearer: &BearerToken,
+    ) -> Result<Self::Authenticated, Self::RefreshError> {
+        let user = self.token_user(bearer).await?;
+        self.authenticate(user)
+    }
+
+    async fn current_user(
+        &self,
+        bearer: &BearerToken,
+    ) -> Result<PublicUser, Self::CurrentUserError> {
+        self.token_user(bearer).await
+    }
+
+    async fn lookup_user(
+        &self,
+        bearer: &BearerToken,
+        identifier: &str,
+    ) -> Result<Option<PublicUser>, Self::LookupError> {
+        self.token_user(bearer).await?;
+        let identifier = identifier.to_string();
+        let store = self.store.clone();
+        web::block(move || store.get_user(&identifier)).await?
+    }
+}
+
+/// A [`PublicUser`] that logged in or refreshed their token
+#[derive(Debug)]
+pub struct AuthenticatedPublicUser {
+    user: PublicUser,
+    bearer: BearerToken,
+}
+
+impl AuthenticatedPublicUser {
+    /// The user and their bearer token
+    pub fn into_parts(self) -> (PublicUser, BearerToken) {
+        (self.user, self.bearer)
+    }
+}
+
+impl Deref for AuthenticatedPublicUser {
+    type Target = PublicUser;
+
+    fn deref(&self) -> &Self::Target {
+        &self.user
+    }
+}
+
+impl DerefMut for AuthenticatedPublicUser {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.user
+    }
+}
+
+impl AuthenticatedUser<PublicUser> for AuthenticatedPublicUser {
+    fn bearer(&self) -> &BearerToken {
+        &self.bearer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::InMemoryUserStore;
+    use crate::store::store_data;
+    use users_api::error::ErrorCode;
+
+    fn service() -> LocalUserService {
+        LocalUserService::new(
+            Data::new(Authenticator::new(b"secret")),
+            Data::new(PasswordAuth::new()),
+            store_data(InMemoryUserStore::new()),
+        )
+    }
+
+    #[actix_web::test]
+    async fn serves_users_in_process() {
+        let service = service();
+        let user = service
+            .sign_up("a@example.com", "a", "password")
+            .await
+            .unwrap();
+        assert_eq!(user.username(), "a");
+        let error = service
+            .sign_up("b@example.com", "b@c", "password")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ServiceError::Invalid(_)), "{error:?}");
+        let error = service.log_in("a", &[0xff]).await.unwrap_err();
+        assert!(matches!(
+            error,
+            ServiceError::Auth(AuthError::PasswordError(_))
+        ));
+
+        let logged_in = service.log_in("a@example.com", b"password").await.unwrap();
+        let refreshed = service.refresh(logged_in.bearer()).await.unwrap();
+        let current = service.current_user(refreshed.bearer()).await.unwrap();
+        assert_eq!(current.email().as_ref(), "a@example.com");
+        let found = service.lookup_user(logged_in.bearer(), "a").await.unwrap();
+        assert_eq!(found.map(|user| user.id()), Some(user.id()));
+        assert!(service
+            .lookup_user(logged_in.bearer(), "b")
+            .await
+            .unwrap()
+            .is_none());
+
+        service.log_out(refreshed.bearer()).await.unwrap();
+        for bearer in [logged_in.bearer(), refreshed.bearer()] {
+            let error = service.current_user(bearer).await.unwrap_err();
+            assert!(matches!(&error, ServiceError::Auth(e) if e.code() == ErrorCode::TokenRevoked));
+        }
+    }
+}
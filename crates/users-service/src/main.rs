@@ -1,49 +1,108 @@
-use actix_web::http::header::{Header, TryIntoHeaderValue};
 use actix_web::middleware::Logger;
-use actix_web::web::{Data, Query};
-use actix_web::{post, App, HttpResponse, HttpServer, Responder};
-use argon2::password_hash::SaltString;
-use chrono::Duration;
-use clap::Parser;
-use diesel::backend::Backend;
-use diesel::r2d2::ConnectionManager;
-use diesel::{Connection, MysqlConnection};
-use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use actix_web::web::Data;
+use actix_web::{App, HttpServer};
+use clap::{Parser, Subcommand};
 use dotenvy::dotenv;
-use openssl::ssl::{SslAcceptor, SslMethod};
-use r2d2::{ManageConnection, Pool};
-use serde::Deserialize;
-use std::env;
+use serde::Serialize;
 use std::error::Error;
 use tracing::info;
 
-use crate::actions::{create_user, login_user};
-use crate::authenticator::{validate_token, Authenticator};
-use crate::user::PublicUser;
-use common::cli::{CommonArgs, SecurityArgs, SecurityBuilderError};
+use common::cli::{CommonArgs, ConfigArgs, DatabaseArgs, LoggingArgs, SecurityArgs};
+use common::config::{load, to_redacted_toml, SecurityBuilderError};
+use common::health::Health;
+use common::metrics::RequestMetrics;
+use common::shutdown::Shutdown;
+use common::trace_context::RequestTracing;
 use common::logging::init_logging;
 use users_api::auth::PasswordAuth;
-use users_api::error::AuthError;
-use users_api::header::Authorization;
-use users_api::EmailAddress;
-
-mod actions;
-mod authenticator;
-mod schema;
-mod tokens;
-mod user;
-
-pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
-
-type Database = Pool<ConnectionManager<MysqlConnection>>;
+use users_service::admin::{TokenCommand, UserCommand};
+use users_service::authenticator::Authenticator;
+use users_service::config::UsersServiceConfig;
+use users_service::db::{establish_connection, establish_router};
+use users_service::health::{DatabaseCheck, MigrationCheck};
+use users_service::metrics::PoolCollector;
+use users_service::migrate::{self, MigrateCommand};
+use users_service::store::{store_data, DieselUserStore};
+use users_service::user::PublicUser;
 
 /// Launches the auth/user service
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
     #[clap(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Used when no subcommand is given
+    #[clap(flatten)]
+    serve: ServeArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs the service. This is the default.
+    Serve(ServeArgs),
+    /// Manages the database schema
+    Migrate(MigrateArgs),
+    /// Manages users directly in the database
+    User(UserArgs),
+    /// Works with bearer tokens
+    Token(TokenArgs),
+}
+
+#[derive(clap::Args, Serialize)]
+struct ServeArgs {
+    #[clap(flatten)]
+    #[serde(flatten)]
     common: CommonArgs,
     #[clap(flatten)]
+    #[serde(rename = "tls")]
     security: SecurityArgs,
+    #[clap(flatten)]
+    database: DatabaseArgs,
+    /// Don't apply pending migrations, and refuse to start if there are any
+    #[clap(long)]
+    #[serde(skip)]
+    no_migrate: bool,
+}
+
+#[derive(clap::Args, Serialize)]
+struct MigrateArgs {
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: MigrateCommand,
+    #[clap(flatten)]
+    logging: LoggingArgs,
+    #[clap(flatten)]
+    database: DatabaseArgs,
+}
+
+#[derive(clap::Args, Serialize)]
+struct UserArgs {
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: UserCommand,
+    /// Prints json instead of text
+    #[clap(long, global = true)]
+    #[serde(skip)]
+    json: bool,
+    #[clap(flatten)]
+    logging: LoggingArgs,
+    #[clap(flatten)]
+    database: DatabaseArgs,
+}
+
+#[derive(clap::Args, Serialize)]
+struct TokenArgs {
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: TokenCommand,
+    /// Prints json instead of text
+    #[clap(long, global = true)]
+    #[serde(skip)]
+    json: bool,
+    #[clap(flatten)]
+    logging: LoggingArgs,
 }
 
 #[actix_web::main]
@@ -51,29 +110,91 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let path = dotenv().ok();
 
     let cli = Args::parse();
+    let command = cli.command.unwrap_or(Command::Serve(cli.serve));
+
+    let config: UsersServiceConfig = match &command {
+        Command::Serve(args) => load(&cli.config, args),
+        Command::Migrate(args) => load(&cli.config, args),
+        Command::User(args) => load(&cli.config, args),
+        Command::Token(args) => load(&cli.config, args),
+    }?;
+    if cli.config.print_config {
+        print!("{}", to_redacted_toml(&config)?);
+        return Ok(());
+    }
 
-    init_logging(&cli.common.logging);
+    init_logging(&config.logging, env!("CARGO_PKG_NAME"))?;
     info!("loaded {:?} into env", path);
 
-    let secret = b"password";
-    let authenticator = Data::new(Authenticator::<PublicUser>::new(secret));
+    match command {
+        Command::Serve(args) => serve(config, args.no_migrate).await,
+        Command::Migrate(args) => {
+            let pool = establish_connection(&config.database)?;
+            args.command.run(&mut *pool.get()?)?;
+            Ok(())
+        }
+        Command::User(args) => {
+            let pool = establish_connection(&config.database)?;
+            let mut conn = pool.get()?;
+            migrate::ensure_up_to_date(&mut conn)?;
+            args.command
+                .run(&mut conn, &PasswordAuth::new(), args.json)?;
+            Ok(())
+        }
+        Command::Token(args) => {
+            let auth = Authenticator::<PublicUser>::new(config.auth.token_secret.expose_secret().as_bytes());
+            args.command.run(&auth, args.json)?;
+            Ok(())
+        }
+    }
+}
+
+async fn serve(config: UsersServiceConfig, no_migrate: bool) -> Result<(), Box<dyn Error>> {
+    let authenticator = Data::new(Authenticator::<PublicUser>::new(
+        config.auth.token_secret.expose_secret().as_bytes(),
+    ));
 
     let passwords = PasswordAuth::new();
 
-    let mut pool = establish_connection();
+    let db = establish_router(&config.database)?;
+    {
+        let mut conn = db.primary().get()?;
+        if no_migrate {
+            migrate::ensure_up_to_date(&mut conn)?;
+        } else {
+            for version in migrate::up(&mut conn)? {
+                info!("applied migration {version}");
+            }
+        }
+    }
+    let shutdown = Shutdown::new(&config.shutdown);
+    let health = Data::new(
+        Health::new()
+            .with_check(shutdown.readiness())
+            .with_check(DatabaseCheck(db.clone()))
+            .with_check(MigrationCheck(db.primary().clone())),
+    );
+    prometheus::register(Box::new(PoolCollector::new(db.clone())))?;
+    let store = store_data(DieselUserStore::new(db));
 
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(RequestMetrics)
             .wrap(Logger::default())
+            .wrap(RequestTracing)
             .app_data(authenticator.clone())
             .app_data(Data::new(passwords.clone()))
-            .app_data(Data::new(pool.clone()))
-            .service(validate_token)
-            .service(create_user)
-            .service(login_user)
-    });
-    let addr = (cli.common.server.ip, cli.common.server.port);
-    let binded = match cli.security.ssl_acceptor() {
+            .app_data(store.clone())
+            .app_data(health.clone())
+            .configure(common::health::configure)
+            .configure(common::metrics::configure)
+            .configure(users_service::configure)
+            .configure(users_service::openapi::configure)
+    })
+    .disable_signals()
+    .shutdown_timeout(shutdown.grace_period().as_secs());
+    let addr = (config.server.ip, config.server.port);
+    let binded = match config.tls.ssl_acceptor() {
         Ok(security) => {
             info!("starting tls server at {:?}", addr);
             server.bind_openssl(addr, security)?
@@ -87,40 +208,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     };
 
-    binded.run().await?;
-
-    Ok(())
-}
-
-fn establish_connection() -> Pool<ConnectionManager<MysqlConnection>> {
-    let url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let mut manager = ConnectionManager::<MysqlConnection>::new(url);
-
-    let pool = r2d2::Pool::builder()
-        .build(manager)
-        .expect("should be a valid connection");
-
-    {
-        let mut conn = pool.get().expect("couldn't get db connection from pool");
-        run_migrations(&mut conn).expect("could not run migrations");
-    }
-
-    pool
-}
-
-fn run_migrations<Db: Backend>(
-    connection: &mut impl MigrationHarness<Db>,
-) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-    // This will run the necessary migrations.
-    //
-    // See the documentation for `MigrationHarness` for
-    // all available methods.
-    connection.run_pending_migrations(MIGRATIONS)?;
+    shutdown.run(binded.run()).await?;
 
     Ok(())
 }
-
-#[derive(Debug, Deserialize)]
-struct GuestOptions {
-    expires_after: Option<u64>,
-}
@@ -0,0 +1,122 @@
+//! Metrics of the users service
+
+use crate::db::{Database, DatabaseRouter};
+use crate::error::ServiceError;
+use common::metrics::counter_vec;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{IntCounterVec, IntGaugeVec};
+use std::sync::OnceLock;
+use users_api::error::AuthError;
+use users_api::scope::Scope;
+
+/// Counters of what the users service has done
+pub struct UsersMetrics {
+    signups: IntCounterVec,
+    logins: IntCounterVec,
+    tokens_issued: IntCounterVec,
+}
+
+/// The counters of the users service, registered on first use
+pub fn metrics() -> &'static UsersMetrics {
+    static METRICS: OnceLock<UsersMetrics> = OnceLock::new();
+    METRICS.get_or_init(|| UsersMetrics {
+        signups: counter_vec(
+            "users_signups_total",
+            "Accounts created, by whether they were upgraded from a guest",
+            &["source"],
+        ),
+        logins: counter_vec(
+            "users_logins_total",
+            "Login attempts, by outcome",
+            &["outcome"],
+        ),
+        tokens_issued: counter_vec(
+            "users_tokens_issued_total",
+            "Bearer tokens issued, by scope",
+            &["scope"],
+        ),
+    })
+}
+
+impl UsersMetrics {
+    /// Counts an account created directly, or by upgrading a guest
+    pub fn signup(&self, from_guest: bool) {
+        let source = if from_guest { "guest" } else { "direct" };
+        self.signups.with_label_values(&[source]).inc();
+    }
+
+    /// Counts a login attempt that got as far as checking credentials
+    pub fn login<T>(&self, result: &Result<T, ServiceError>) {
+        let outcome = match result {
+            Ok(_) => "success",
+            Err(ServiceError::Auth(AuthError::NoUserFound(_))) => "unknown_user",
+            Err(ServiceError::Auth(AuthError::PasswordError(_))) => "wrong_password",
+            Err(ServiceError::Banned(_)) => "banned",
+            Err(_) => "error",
+        };
+        self.logins.with_label_values(&[outcome]).inc();
+    }
+
+    /// Counts a login attempt without usable credentials
+    pub fn invalid_login(&self) {
+        self.logins.with_label_values(&["invalid_request"]).inc();
+    }
+
+    /// Counts an issued token
+    pub fn token_issued(&self, scope: Scope) {
+        self.tokens_issued
+            .with_label_values(&[&scope.to_string()])
+            .inc();
+    }
+}
+
+/// Reports how many connections each database pool has open, in use, and at most
+#[derive(Debug)]
+pub struct PoolCollector {
+    db: DatabaseRouter,
+    connections: IntGaugeVec,
+}
+
+impl PoolCollector {
+    /// Creates a collector, which still has to be [registered](prometheus::register)
+    pub fn new(db: DatabaseRouter) -> Self {
+        let connections = IntGaugeVec::new(
+            prometheus::Opts::new(
+                "db_pool_connections",
+                "Connections of a database pool, by state",
+            ),
+            &["pool", "state"],
+        )
+        .expect("invalid gauge");
+        Self { db, connections }
+    }
+
+    fn observe(&self, name: &str, pool: &Database) {
+        let state = pool.state();
+        let gauges = [
+            ("idle", state.idle_connections),
+            ("in_use", state.connections - state.idle_connections),
+            ("max", pool.max_size()),
+        ];
+        for (label, value) in gauges {
+            self.connections
+                .with_label_values(&[name, label])
+                .set(value.into());
+        }
+    }
+}
+
+impl Collector for PoolCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.connections.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.observe("primary", self.db.primary());
+        if let Some(replica) = self.db.replica() {
+            self.observe("replica", replica);
+        }
+        self.connections.collect()
+    }
+}
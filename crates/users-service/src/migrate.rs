@@ -0,0 +1,121 @@
+//! Reviewing, applying and reverting schema migrations
+
+use crate::db::{DbConnection, MIGRATIONS};
+use clap::Subcommand;
+use diesel::migration::MigrationSource;
+use diesel::Connection;
+use diesel_migrations::MigrationHarness;
+use std::error::Error;
+
+type Backend = <DbConnection as Connection>::Backend;
+
+/// Why migrations could not be checked or run
+#[derive(Debug, thiserror::Error)]
+pub enum MigrateError {
+    #[error("could not get a database connection: {0}")]
+    Pool(#[from] r2d2::Error),
+    #[error(transparent)]
+    Migration(Box<dyn Error + Send + Sync>),
+    #[error("the database schema is behind, pending migrations: {}", .0.join(", "))]
+    Pending(Vec<String>),
+}
+
+/// A migration, and whether it has been applied
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub name: String,
+    pub applied: bool,
+}
+
+/// Lists every migration, oldest first
+pub fn status(conn: &mut DbConnection) -> Result<Vec<MigrationStatus>, MigrateError> {
+    let applied = conn.applied_migrations().map_err(MigrateError::Migration)?;
+    let mut migrations =
+        MigrationSource::<Backend>::migrations(&MIGRATIONS).map_err(MigrateError::Migration)?;
+    migrations.sort_by(|a, b| a.name().version().cmp(&b.name().version()));
+    Ok(migrations
+        .iter()
+        .map(|migration| MigrationStatus {
+            name: migration.name().to_string(),
+            applied: applied.contains(&migration.name().version()),
+        })
+        .collect())
+}
+
+/// Runs every pending migration, returning their versions
+pub fn up(conn: &mut DbConnection) -> Result<Vec<String>, MigrateError> {
+    let versions = conn
+        .run_pending_migrations(MIGRATIONS)
+        .map_err(MigrateError::Migration)?;
+    Ok(versions.iter().map(ToString::to_string).collect())
+}
+
+/// Reverts the last applied migration, returning its version
+pub fn down(conn: &mut DbConnection) -> Result<String, MigrateError> {
+    let version = conn
+        .revert_last_migration(MIGRATIONS)
+        .map_err(MigrateError::Migration)?;
+    Ok(version.to_string())
+}
+
+/// Reverts then re-applies the last applied migration, returning its version
+pub fn redo(conn: &mut DbConnection) -> Result<String, MigrateError> {
+    down(conn)?;
+    let version = conn
+        .run_next_migration(MIGRATIONS)
+        .map_err(MigrateError::Migration)?;
+    Ok(version.to_string())
+}
+
+/// Fails with [`MigrateError::Pending`] if any migration hasn't been applied
+pub fn ensure_up_to_date(conn: &mut DbConnection) -> Result<(), MigrateError> {
+    let pending: Vec<_> = status(conn)?
+        .into_iter()
+        .filter(|migration| !migration.applied)
+        .map(|migration| migration.name)
+        .collect();
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(MigrateError::Pending(pending))
+    }
+}
+
+/// The `migrate` subcommands
+#[derive(Debug, Clone, Subcommand)]
+pub enum MigrateCommand {
+    /// Lists every migration and whether it has been applied
+    Status,
+    /// Applies every pending migration
+    Up,
+    /// Reverts the last applied migration
+    Down,
+    /// Reverts then re-applies the last applied migration
+    Redo,
+}
+
+impl MigrateCommand {
+    /// Runs the subcommand, printing what was done
+    pub fn run(&self, conn: &mut DbConnection) -> Result<(), MigrateError> {
+        match self {
+            MigrateCommand::Status => {
+                for migration in status(conn)? {
+                    let mark = if migration.applied { "x" } else { " " };
+                    println!("[{mark}] {}", migration.name);
+                }
+            }
+            MigrateCommand::Up => {
+                let applied = up(conn)?;
+                if applied.is_empty() {
+                    println!("the database is up to date");
+                }
+                for version in applied {
+                    println!("applied {version}");
+                }
+            }
+            MigrateCommand::Down => println!("reverted {}", down(conn)?),
+            MigrateCommand::Redo => println!("redid {}", redo(conn)?),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+//! The OpenAPI document of the users service, served at `/openapi.json` and browsable at `/docs`.
+//!
+//! `openapi.json` at the root of this crate is a copy of the document, checked by a test. Run
+//! `UPDATE_OPENAPI=1 cargo test -p users-service openapi` to update it after changing the api.
+
+use crate::actions::{CreateUserBody, UserInfo};
+use crate::guest::GuestInfo;
+use crate::preferences::Preferences;
+use actix_web::http::header::ContentType;
+use actix_web::web::{self, Json, ServiceConfig};
+use actix_web::HttpResponse;
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::openapi::{
+    ArrayBuilder, KnownFormat, ObjectBuilder, Ref, RefOr, Schema, SchemaFormat, SchemaType,
+};
+use utoipa::{Modify, OpenApi, ToSchema};
+
+/// The OpenAPI document of every route registered by [`configure`](crate::configure)
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        crate::authenticator::validate_token,
+        crate::authenticator::get_claims,
+        crate::actions::create_user,
+        crate::actions::login_user,
+        crate::actions::get_profile,
+        crate::actions::log_out,
+        crate::actions::refresh_token,
+        crate::actions::lookup_user,
+        crate::guest::create_guest,
+        crate::guest::upgrade_guest,
+        crate::actions::get_preferences,
+        crate::actions::set_preferences,
+    ),
+    components(schemas(
+        CreateUserBody,
+        UserInfo,
+        GuestInfo,
+        Preferences,
+        ExpirationTime,
+        Claims
+    )),
+    modifiers(&Finish),
+    info(description = "Manages accounts, guests and their bearer tokens"),
+    tags(
+        (name = "users", description = "Accounts and their preferences"),
+        (name = "guests", description = "Sessions without an account"),
+        (name = "tokens", description = "Bearer tokens"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Documents [`users_api::ExpirationTime`], whose type is not ours to derive a schema for
+pub struct ExpirationTime;
+
+impl<'s> ToSchema<'s> for ExpirationTime {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        (
+            "ExpirationTime",
+            ObjectBuilder::new()
+                .schema_type(SchemaType::String)
+                .format(Some(SchemaFormat::KnownFormat(KnownFormat::DateTime)))
+                .description(Some("When a token expires"))
+                .into(),
+        )
+    }
+}
+
+/// Documents [`users_api::claims::Claims`], whose type is not ours to derive a schema for
+pub struct Claims;
+
+impl<'s> ToSchema<'s> for Claims {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        let string = || ObjectBuilder::new().schema_type(SchemaType::String);
+        (
+            "Claims",
+            ObjectBuilder::new()
+                .description(Some("Who a bearer token was issued to, and what it grants"))
+                .property(
+                    "subject",
+                    string().description(Some("The email of a user, or the id of a guest")),
+                )
+                .required("subject")
+                .property(
+                    "user_id",
+                    ObjectBuilder::new()
+                        .schema_type(SchemaType::Integer)
+                        .format(Some(SchemaFormat::KnownFormat(KnownFormat::Int64)))
+                        .nullable(true)
+                        .description(Some("The id of the user, null for guests")),
+                )
+                .property(
+                    "username",
+                    string()
+                        .nullable(true)
+                        .description(Some("The username of the user, null for guests")),
+                )
+                .property(
+                    "scopes",
+                    ArrayBuilder::new().items(string().enum_values(Some(["user", "guest"]))),
+                )
+                .required("scopes")
+                .property("expires", Ref::from_schema_name("ExpirationTime"))
+                .required("expires")
+                .into(),
+        )
+    }
+}
+
+/// Adds the `basic` and `bearer` schemes used by the routes, and drops the empty license read
+/// from `Cargo.toml`
+struct Finish;
+
+impl Modify for Finish {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi.info.license = None;
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "basic",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
+/// Loads the document into a docs page
+static DOCS_PAGE: &str = r#"<!doctype html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>users-service</title>
+    <script type="module" src="https://unpkg.com/rapidoc/dist/rapidoc-min.js"></script>
+</head>
+<body>
+    <rapi-doc spec-url="/openapi.json" render-style="read" allow-try="true"></rapi-doc>
+</body>
+</html>
+"#;
+
+/// Registers `/openapi.json` and `/docs`
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.route(
+        "/openapi.json",
+        web::get().to(|| async { Json(ApiDoc::openapi()) }),
+    )
+    .route(
+        "/docs",
+        web::get().to(|| async {
+            HttpResponse::Ok()
+                .content_type(ContentType::html())
+                .body(DOCS_PAGE)
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authenticator::Authenticator;
+    use crate::store::memory::InMemoryUserStore;
+    use crate::store::store_data;
+    use crate::user::PublicUser;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::web::Data;
+    use actix_web::App;
+    use std::path::Path;
+    use users_api::auth::PasswordAuth;
+    use utoipa::openapi::PathItemType;
+
+    #[test]
+    fn committed_openapi_is_up_to_date() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("openapi.json");
+        let spec = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            std::fs::write(&path, &spec).unwrap();
+        }
+        let committed = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            committed == spec,
+            "{path:?} is out of date, update it with `UPDATE_OPENAPI=1 cargo test -p users-service openapi`"
+        );
+    }
+
+    #[actix_web::test]
+    async fn documented_routes_exist() {
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(Authenticator::<PublicUser>::new(b"password")))
+                .app_data(Data::new(PasswordAuth::new()))
+                .app_data(store_data(InMemoryUserStore::new()))
+                .configure(crate::configure)
+                .configure(configure),
+        )
+        .await;
+
+        for (path, item) in ApiDoc::openapi().paths.paths {
+            for operation in item.operations.keys() {
+                let method = match operation {
+                    PathItemType::Get => Method::GET,
+                    PathItemType::Post => Method::POST,
+                    PathItemType::Put => Method::PUT,
+                    PathItemType::Delete => Method::DELETE,
+                    _ => panic!("{path} has an operation other than get, post, put or delete"),
+                };
+                let req = TestRequest::default()
+                    .method(method.clone())
+                    .uri(&path)
+                    .to_request();
+                let resp = call_service(&app, req).await;
+                assert_ne!(
+                    resp.status(),
+                    StatusCode::NOT_FOUND,
+                    "{method} {path} is documented but not routed"
+                );
+            }
+        }
+
+        let req = TestRequest::get().uri("/openapi.json").to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,26 @@
+//! Preferences kept for users and guests
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+/// Client-defined preferences, stored as a json object
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(transparent)]
+#[schema(value_type = Object, example = json!({ "theme": "dark" }))]
+pub struct Preferences(Map<String, Value>);
+
+impl Preferences {
+    /// Reads preferences from a nullable text column. Missing or malformed values are treated
+    /// as no preferences being set.
+    pub fn from_column(column: Option<&str>) -> Self {
+        column
+            .and_then(|text| serde_json::from_str(text).ok())
+            .unwrap_or_default()
+    }
+
+    /// Converts preferences into the text stored in the database
+    pub fn to_column(&self) -> String {
+        Value::Object(self.0.clone()).to_string()
+    }
+}
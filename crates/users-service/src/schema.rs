@@ -1,5 +1,14 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    guest (id) {
+        #[max_length = 36]
+        id -> Varchar,
+        expires_at -> Timestamp,
+        preferences -> Nullable<Text>,
+    }
+}
+
 diesel::table! {
     user (id) {
         id -> Bigint,
@@ -8,5 +17,14 @@ diesel::table! {
         #[max_length = 64]
         username -> Varchar,
         password_hash -> Text,
+        preferences -> Nullable<Text>,
+        admin -> Bool,
+        banned -> Bool,
+        tokens_revoked_at -> Nullable<Timestamp>,
     }
 }
+
+diesel::allow_tables_to_appear_in_same_query!(
+    guest,
+    user,
+);
@@ -0,0 +1,220 @@
+//! Where the request handlers keep users and guests
+
+use crate::db::{DatabaseRouter, PooledDbConnection};
+use crate::error::ServiceError;
+use crate::guest::Guest;
+use crate::preferences::Preferences;
+use crate::tokens::AuthenticatedUserToken;
+use crate::user::PublicUser;
+use actix_web::web::Data;
+use chrono::Duration;
+use common::repo::RepositoryError;
+use std::fmt::Debug;
+use std::sync::Arc;
+use users_api::auth::PasswordAuth;
+use users_api::error::AuthError;
+use users_api::User;
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod memory;
+
+/// Stores users and guests.
+///
+/// Handlers expect a `Data<dyn UserStore>`, which can be created with [`store_data`].
+pub trait UserStore: Debug + Send + Sync {
+    /// Creates a new user with an already hashed password
+    fn create_user(
+        &self,
+        email: &str,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<PublicUser, ServiceError>;
+
+    /// Finds a user by either their email or their username
+    fn get_user(&self, identifier: &str) -> Result<Option<PublicUser>, ServiceError>;
+
+    /// Checks a password against the one stored for a user
+    fn verify_password(
+        &self,
+        user: &PublicUser,
+        auth: &PasswordAuth,
+        password: &str,
+    ) -> Result<(), ServiceError>;
+
+    /// Gets the preferences of a user
+    fn user_preferences(&self, user: &PublicUser) -> Result<Preferences, ServiceError>;
+
+    /// Replaces the preferences of a user
+    fn set_user_preferences(
+        &self,
+        user: &PublicUser,
+        preferences: &Preferences,
+    ) -> Result<(), ServiceError>;
+
+    /// Creates a new guest that expires after the given duration
+    fn create_guest(&self, expires_in: Duration) -> Result<Guest, ServiceError>;
+
+    /// Gets a guest by its id, if it still exists and has not expired
+    fn get_guest(&self, id: &str) -> Result<Option<Guest>, ServiceError>;
+
+    /// Replaces the preferences of a guest
+    fn set_guest_preferences(
+        &self,
+        guest: &Guest,
+        preferences: &Preferences,
+    ) -> Result<(), ServiceError>;
+
+    /// Turns a guest into a full user with an already hashed password, keeping its preferences
+    fn upgrade_guest(
+        &self,
+        guest: Guest,
+        email: &str,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<PublicUser, ServiceError>;
+
+    /// Revokes every token issued to a user until now
+    fn revoke_tokens(&self, user: &PublicUser) -> Result<(), ServiceError>;
+
+    /// Finds the user a token was issued to, failing if the token has since been revoked or the
+    /// user banned
+    fn token_user(&self, token: &AuthenticatedUserToken) -> Result<PublicUser, ServiceError> {
+        let user = self
+            .get_user(token.subject())?
+            .ok_or_else(|| AuthError::NoUserFound(token.subject().to_string()))?;
+        token.ensure_not_revoked(user.tokens_revoked_at())?;
+        if user.is_banned() {
+            return Err(ServiceError::Banned(user.username().to_string()));
+        }
+        Ok(user)
+    }
+
+    /// Finds the guest a token was issued to, failing if it has since been upgraded or purged
+    fn token_guest(&self, token: &AuthenticatedUserToken) -> Result<Guest, ServiceError> {
+        Ok(self
+            .get_guest(token.subject())?
+            .ok_or_else(|| AuthError::NoUserFound(token.subject().to_string()))?)
+    }
+}
+
+/// Wraps a store so it can be given to the app as `Data<dyn UserStore>`
+pub fn store_data<S: UserStore + 'static>(store: S) -> Data<dyn UserStore> {
+    Data::from(Arc::new(store) as Arc<dyn UserStore>)
+}
+
+/// Keeps users and guests in the database, reading from a replica when one is configured
+#[derive(Debug, Clone)]
+pub struct DieselUserStore {
+    db: DatabaseRouter,
+}
+
+impl DieselUserStore {
+    /// Creates a store using connections from the given pool or router
+    pub fn new(db: impl Into<DatabaseRouter>) -> Self {
+        Self { db: db.into() }
+    }
+
+    /// Gets a connection for reading the records of a user's email or username, or a guest's id
+    fn read(&self, subject: &str) -> Result<PooledDbConnection, ServiceError> {
+        self.db
+            .read(subject)
+            .map_err(|e| ServiceError::Repository(RepositoryError::Pool(e)))
+    }
+
+    fn write(&self) -> Result<PooledDbConnection, ServiceError> {
+        self.db
+            .write()
+            .map_err(|e| ServiceError::Repository(RepositoryError::Pool(e)))
+    }
+
+    /// Records that a user was written, under both identifiers they can be read by
+    fn wrote_user(&self, user: &PublicUser) {
+        self.db.wrote(user.email().as_ref());
+        self.db.wrote(user.username());
+    }
+}
+
+impl UserStore for DieselUserStore {
+    fn create_user(
+        &self,
+        email: &str,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<PublicUser, ServiceError> {
+        let mut conn = self.write()?;
+        let user = PublicUser::create_new_user(&mut conn, email, username, password_hash)?;
+        self.wrote_user(&user);
+        Ok(user)
+    }
+
+    fn get_user(&self, identifier: &str) -> Result<Option<PublicUser>, ServiceError> {
+        Ok(PublicUser::get_user(
+            &mut *self.read(identifier)?,
+            identifier,
+        )?)
+    }
+
+    fn verify_password(
+        &self,
+        user: &PublicUser,
+        auth: &PasswordAuth,
+        password: &str,
+    ) -> Result<(), ServiceError> {
+        user.verify_password(&mut *self.read(user.email().as_ref())?, auth, password)
+    }
+
+    fn user_preferences(&self, user: &PublicUser) -> Result<Preferences, ServiceError> {
+        Ok(user.preferences(&mut *self.read(user.email().as_ref())?)?)
+    }
+
+    fn set_user_preferences(
+        &self,
+        user: &PublicUser,
+        preferences: &Preferences,
+    ) -> Result<(), ServiceError> {
+        user.set_preferences(&mut *self.write()?, preferences)?;
+        self.wrote_user(user);
+        Ok(())
+    }
+
+    fn create_guest(&self, expires_in: Duration) -> Result<Guest, ServiceError> {
+        let guest = Guest::create_new_guest(&mut *self.write()?, expires_in)?;
+        self.db.wrote(guest.id());
+        Ok(guest)
+    }
+
+    fn get_guest(&self, id: &str) -> Result<Option<Guest>, ServiceError> {
+        Ok(Guest::get_guest(&mut *self.read(id)?, id)?)
+    }
+
+    fn set_guest_preferences(
+        &self,
+        guest: &Guest,
+        preferences: &Preferences,
+    ) -> Result<(), ServiceError> {
+        guest.set_preferences(&mut *self.write()?, preferences)?;
+        self.db.wrote(guest.id());
+        Ok(())
+    }
+
+    fn upgrade_guest(
+        &self,
+        guest: Guest,
+        email: &str,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<PublicUser, ServiceError> {
+        let mut conn = self.write()?;
+        let id = guest.id().to_string();
+        let user = guest.upgrade(&mut conn, email, username, password_hash)?;
+        self.db.wrote(&id);
+        self.wrote_user(&user);
+        Ok(user)
+    }
+
+    fn revoke_tokens(&self, user: &PublicUser) -> Result<(), ServiceError> {
+        user.clone().revoke_tokens(&mut *self.write()?)?;
+        self.wrote_user(user);
+        Ok(())
+    }
+}
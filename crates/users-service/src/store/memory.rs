@@ -0,0 +1,392 @@
+//! A [`UserStore`] that keeps everything in memory, for tests
+
+use crate::error::ServiceError;
+use crate::guest::Guest;
+use crate::preferences::Preferences;
+use crate::store::UserStore;
+use crate::user::PublicUser;
+use chrono::{Duration, Utc};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use users_api::auth::PasswordAuth;
+use users_api::error::AuthError;
+use users_api::{EmailAddress, User};
+
+/// Keeps users and guests in memory. Nothing is persisted once the store is dropped.
+#[derive(Debug, Default)]
+pub struct InMemoryUserStore {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_id: i64,
+    users: HashMap<i64, StoredUser>,
+    guests: HashMap<String, Guest>,
+}
+
+#[derive(Debug)]
+struct StoredUser {
+    user: PublicUser,
+    password_hash: String,
+    preferences: Preferences,
+}
+
+impl InMemoryUserStore {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Inner {
+    fn find(&self, identifier: &str) -> Option<&StoredUser> {
+        self.users.values().find(|stored| {
+            stored.user.email().as_ref() == identifier || stored.user.username() == identifier
+        })
+    }
+
+    fn find_mut(&mut self, user: &PublicUser) -> Result<&mut StoredUser, ServiceError> {
+        self.users
+            .get_mut(&user.id())
+            .ok_or_else(|| AuthError::NoUserFound(user.username().to_string()).into())
+    }
+
+    fn insert(
+        &mut self,
+        email: &str,
+        username: &str,
+        password_hash: &str,
+        preferences: Preferences,
+    ) -> Result<PublicUser, ServiceError> {
+        for identifier in [email, username] {
+            if self.find(identifier).is_some() {
+                return Err(ServiceError::UserExists(identifier.to_string()));
+            }
+        }
+
+        self.next_id += 1;
+        let user = PublicUser::with_id(
+            self.next_id,
+            EmailAddress::new_unchecked(email),
+            username.to_string(),
+        );
+        self.users.insert(
+            user.id(),
+            StoredUser {
+                user: user.clone(),
+                password_hash: password_hash.to_string(),
+                preferences,
+            },
+        );
+        Ok(user)
+    }
+}
+
+impl UserStore for InMemoryUserStore {
+    fn create_user(
+        &self,
+        email: &str,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<PublicUser, ServiceError> {
+        self.inner
+            .lock()
+            .insert(email, username, password_hash, Preferences::default())
+    }
+
+    fn get_user(&self, identifier: &str) -> Result<Option<PublicUser>, ServiceError> {
+        Ok(self
+            .inner
+            .lock()
+            .find(identifier)
+            .map(|stored| stored.user.clone()))
+    }
+
+    fn verify_password(
+        &self,
+        user: &PublicUser,
+        auth: &PasswordAuth,
+        password: &str,
+    ) -> Result<(), ServiceError> {
+        let password_hash = self.inner.lock().find_mut(user)?.password_hash.clone();
+        Ok(auth.verify_password(password.as_bytes(), &password_hash)?)
+    }
+
+    fn user_preferences(&self, user: &PublicUser) -> Result<Preferences, ServiceError> {
+        Ok(self.inner.lock().find_mut(user)?.preferences.clone())
+    }
+
+    fn set_user_preferences(
+        &self,
+        user: &PublicUser,
+        preferences: &Preferences,
+    ) -> Result<(), ServiceError> {
+        self.inner.lock().find_mut(user)?.preferences = preferences.clone();
+        Ok(())
+    }
+
+    fn create_guest(&self, expires_in: Duration) -> Result<Guest, ServiceError> {
+        let guest = Guest::new(
+            uuid::Uuid::new_v4().to_string(),
+            Utc::now().naive_utc() + expires_in,
+        );
+        let mut inner = self.inner.lock();
+        inner
+            .guests
+            .retain(|_, existing| existing.expiration_time() >= Utc::now());
+        inner.guests.insert(guest.id().to_string(), guest.clone());
+        Ok(guest)
+    }
+
+    fn get_guest(&self, id: &str) -> Result<Option<Guest>, ServiceError> {
+        Ok(self
+            .inner
+            .lock()
+            .guests
+            .get(id)
+            .filter(|guest| guest.expiration_time() >= Utc::now())
+            .cloned())
+    }
+
+    fn set_guest_preferences(
+        &self,
+        guest: &Guest,
+        preferences: &Preferences,
+    ) -> Result<(), ServiceError> {
+        self.inner
+            .lock()
+            .guests
+            .insert(guest.id().to_string(), guest.with_preferences(preferences));
+        Ok(())
+    }
+
+    fn upgrade_guest(
+        &self,
+        guest: Guest,
+        email: &str,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<PublicUser, ServiceError> {
+        let mut inner = self.inner.lock();
+        let user = inner.insert(email, username, password_hash, guest.preferences())?;
+        inner.guests.remove(guest.id());
+        Ok(user)
+    }
+
+    fn revoke_tokens(&self, user: &PublicUser) -> Result<(), ServiceError> {
+        let mut inner = self.inner.lock();
+        inner
+            .find_mut(user)?
+            .user
+            .set_tokens_revoked_at(Utc::now().naive_utc());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authenticator::Authenticator;
+    use crate::store::store_data;
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::http::StatusCode;
+    use actix_web::web::Data;
+    use actix_web::{test, App, HttpServer};
+    use common::utils::encode_base64;
+    use serde_json::json;
+    use users_api::client::{Client, ClientError};
+    use users_api::error::ErrorCode;
+    use users_api::scope::Scope;
+    use users_api::user_service::{AuthenticatedUser, UserService};
+
+    #[actix_web::test]
+    async fn create_log_in_and_set_preferences() {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(Authenticator::<PublicUser>::new(b"password")))
+                .app_data(Data::new(PasswordAuth::new()))
+                .app_data(store_data(InMemoryUserStore::new()))
+                .configure(crate::configure),
+        )
+        .await;
+
+        let create = || {
+            test::TestRequest::post()
+                .uri("/user/create")
+                .set_json(json!({
+                    "email": "test@example.com",
+                    "username": "test",
+                    "password": "password"
+                }))
+                .to_request()
+        };
+        let resp = test::call_service(&app, create()).await;
+        assert!(resp.status().is_success(), "{:?}", resp.status());
+        let resp = test::call_service(&app, create()).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let req = test::TestRequest::post()
+            .uri("/user/login")
+            .insert_header((
+                AUTHORIZATION,
+                format!("Basic {}", encode_base64(&"test:password")),
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success(), "{:?}", resp.status());
+        let bearer = resp.headers().get(AUTHORIZATION).unwrap().clone();
+
+        let req = test::TestRequest::put()
+            .uri("/user/preferences")
+            .insert_header((AUTHORIZATION, bearer.clone()))
+            .set_json(json!({ "theme": "dark" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success(), "{:?}", resp.status());
+
+        let req = test::TestRequest::get()
+            .uri("/user/preferences")
+            .insert_header((AUTHORIZATION, bearer))
+            .to_request();
+        let preferences: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(preferences, json!({ "theme": "dark" }));
+    }
+
+    #[actix_web::test]
+    async fn log_in_with_standard_base64() {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(Authenticator::<PublicUser>::new(b"password")))
+                .app_data(Data::new(PasswordAuth::new()))
+                .app_data(store_data(InMemoryUserStore::new()))
+                .configure(crate::configure),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/user/create")
+            .set_json(json!({
+                "email": "test@example.com",
+                "username": "test",
+                "password": "?>?>:~~~"
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success(), "{:?}", resp.status());
+
+        // encodes with both `+` and `/`, which are not in the url safe alphabet
+        let credentials = encode_base64(&"test:?>?>:~~~");
+        assert!(credentials.contains('+') && credentials.contains('/'));
+        let req = test::TestRequest::post()
+            .uri("/user/login")
+            .insert_header((AUTHORIZATION, format!("Basic {credentials}")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success(), "{:?}", resp.status());
+
+        let req = test::TestRequest::post()
+            .uri("/user/login")
+            .insert_header((AUTHORIZATION, "Basic not base64!"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[actix_web::test]
+    async fn client_covers_every_route() {
+        let store = store_data(InMemoryUserStore::new());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(Data::new(Authenticator::<PublicUser>::new(b"password")))
+                .app_data(Data::new(PasswordAuth::new()))
+                .app_data(store.clone())
+                .configure(crate::configure)
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let client = Client::new(format!("http://{}", server.addrs()[0]));
+        actix_web::rt::spawn(server.run());
+
+        let created = client
+            .sign_up("test@example.com", "test", "password")
+            .await
+            .unwrap();
+        assert_eq!(created.username(), "test");
+        let error = client
+            .sign_up("test@example.com", "other", "password")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ClientError::Validation(_)), "{error:?}");
+        assert_eq!(error.code(), Some(ErrorCode::UserExists));
+        let error = client
+            .sign_up("other@example.com", "at@sign", "password")
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::InvalidRequest));
+
+        let error = client.log_in("test", b"wrong").await.unwrap_err();
+        assert!(matches!(error, ClientError::Auth(_)), "{error:?}");
+        assert_eq!(error.code(), Some(ErrorCode::InvalidCredentials));
+
+        let user = client.log_in("test", b"password").await.unwrap();
+        let bearer = user.bearer().clone();
+        client.validate_token(&bearer).await.unwrap();
+        let claims = client.claims(&bearer).await.unwrap();
+        assert_eq!(claims.username.as_deref(), Some("test"));
+        assert_eq!(claims.scopes, [Scope::User]);
+        let profile = client.profile(&bearer).await.unwrap();
+        assert_eq!(profile.username(), "test");
+        assert_eq!(profile.email().as_ref(), "test@example.com");
+        let found = client
+            .lookup_user(&bearer, "test@example.com")
+            .await
+            .unwrap();
+        assert_eq!(
+            found.map(|user| user.username().to_string()).as_deref(),
+            Some("test")
+        );
+        assert!(client
+            .lookup_user(&bearer, "nobody")
+            .await
+            .unwrap()
+            .is_none());
+
+        let preferences = json!({ "theme": "dark" });
+        let preferences = preferences.as_object().unwrap();
+        client.set_preferences(&bearer, preferences).await.unwrap();
+        assert_eq!(&client.preferences(&bearer).await.unwrap(), preferences);
+
+        client.log_out(&bearer).await.unwrap();
+        let error = client.validate_token(&bearer).await.unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::TokenRevoked));
+        let error = client.profile(&bearer).await.unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::TokenRevoked));
+        let user = client.log_in("test", b"password").await.unwrap();
+        let refreshed = client.refresh(user.bearer()).await.unwrap();
+        assert_eq!(refreshed.username(), "test");
+        client.validate_token(refreshed.bearer()).await.unwrap();
+        client.validate_token(user.bearer()).await.unwrap();
+
+        let guest = client.create_guest(Some(60)).await.unwrap();
+        client
+            .set_preferences(&guest.bearer, preferences)
+            .await
+            .unwrap();
+        let claims = client.claims(&guest.bearer).await.unwrap();
+        assert_eq!((claims.subject, claims.user_id), (guest.id.clone(), None));
+        let error = client.profile(&guest.bearer).await.unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::MissingScope));
+        let error = client.refresh(&guest.bearer).await.unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::MissingScope));
+        let upgraded = client
+            .upgrade_guest(&guest.bearer, "guest@example.com", "guest", "password")
+            .await
+            .unwrap();
+        assert_eq!(upgraded.username(), "guest");
+        assert_eq!(
+            &client.preferences(upgraded.bearer()).await.unwrap(),
+            preferences
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! A real users service, for the tests of services that depend on it.
+//!
+//! Enabled with the `test-util` and `sqlite` features.
+
+use crate::authenticator::Authenticator;
+use crate::db::connect;
+use crate::store::{store_data, DieselUserStore, UserStore};
+use crate::user::PublicUser;
+use actix_web::dev::ServerHandle;
+use actix_web::web::Data;
+use actix_web::{App, HttpServer};
+use tempfile::TempDir;
+use users_api::auth::{LocalAuthService, PasswordAuth};
+use users_api::bearer::BearerToken;
+use users_api::claims::Claims;
+use users_api::client::Client;
+use users_api::test_util::mint_token;
+
+/// The secret the tokens of a [`TestServer`] are signed with
+pub const TOKEN_SECRET: &[u8] = b"test-secret";
+
+/// The users service, listening on an ephemeral port of localhost with a fresh sqlite database.
+///
+/// The database is deleted when the server is dropped.
+#[derive(Debug)]
+pub struct TestServer {
+    url: String,
+    store: Data<dyn UserStore>,
+    handle: ServerHandle,
+    _dir: TempDir,
+}
+
+impl TestServer {
+    /// Starts the server on the current actix runtime, such as the one of `#[actix_web::test]`.
+    ///
+    /// # Panics
+    /// Panics if the database can't be created or no port can be bound.
+    pub fn start() -> Self {
+        let dir = tempfile::tempdir().expect("could not create a directory for the database");
+        let path = dir.path().join("users.db");
+        let pool = connect(path.to_str().expect("paths are utf-8"));
+        let store = store_data(DieselUserStore::new(pool));
+
+        let authenticator = Data::new(Authenticator::<PublicUser>::new(TOKEN_SECRET));
+        let passwords = Data::new(PasswordAuth::new());
+        let app_store = store.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(authenticator.clone())
+                .app_data(passwords.clone())
+                .app_data(app_store.clone())
+                .configure(crate::configure)
+        })
+        .workers(1)
+        .disable_signals()
+        .bind(("127.0.0.1", 0))
+        .expect("could not bind a port");
+        let url = format!("http://{}", server.addrs()[0]);
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        Self {
+            url,
+            store,
+            handle,
+            _dir: dir,
+        }
+    }
+
+    /// The url the server listens on, such as `http://127.0.0.1:34567`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// A client of the server
+    pub fn client(&self) -> Client {
+        Client::new(self.url.clone())
+    }
+
+    /// Validates tokens of the server in-process
+    pub fn auth_service(&self) -> LocalAuthService {
+        LocalAuthService::new(TOKEN_SECRET)
+    }
+
+    /// Signs a token the server accepts, without logging in
+    pub fn mint_token(&self, claims: &Claims) -> BearerToken {
+        mint_token(TOKEN_SECRET, claims)
+    }
+
+    /// The store of the server, to set up users and guests directly
+    pub fn store(&self) -> &dyn UserStore {
+        &**self.store
+    }
+
+    /// Creates a user who can log in with the given password.
+    ///
+    /// # Panics
+    /// Panics if the user can't be created.
+    pub fn create_user(&self, email: &str, username: &str, password: &str) -> PublicUser {
+        let hash = PasswordAuth::new()
+            .hash_password(password.as_bytes())
+            .expect("could not hash the password");
+        self.store
+            .create_user(email, username, &hash)
+            .expect("could not create the user")
+    }
+
+    /// Stops the server, letting it finish the requests being handled
+    pub async fn stop(self) {
+        self.handle.stop(true).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use users_api::auth::AuthService;
+    use users_api::user_service::{AuthenticatedUser, UserService};
+    use users_api::User;
+
+    #[actix_web::test]
+    async fn serves_the_client() {
+        let server = TestServer::start();
+        let user = server.create_user("a@example.com", "a", "password");
+        let client = server.client();
+
+        let logged_in = client.log_in("a", b"password").await.unwrap();
+        assert_eq!(logged_in.username(), "a");
+        let claims = server.auth_service().claims(logged_in.bearer()).unwrap();
+        assert_eq!(claims.user_id, Some(user.id()));
+
+        let minted = server.mint_token(&claims);
+        let profile = client.profile(&minted).await.unwrap();
+        assert_eq!(profile.email().as_ref(), "a@example.com");
+
+        server.stop().await;
+        assert!(client.log_in("a", b"password").await.is_err());
+    }
+}
@@ -1,42 +1,105 @@
 //! Used to define the JWT for bearer auth
 
+use crate::user::PublicUser;
 use chrono::Duration;
-use hmac::digest::KeyInit;
-use hmac::Hmac;
 use serde::{Deserialize, Serialize};
-use sha2::Sha384;
-use std::ops::Add;
-use std::time::{Instant, SystemTime};
-use users_api::bearer::BearerToken;
+use std::time::SystemTime;
+use users_api::claims::Claims;
+use users_api::error::AuthError;
+use users_api::scope::Scope;
 use users_api::{ExpirationTime, User};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthenticatedUserToken {
-    email: String,
+    /// The email of a user, or the id of a guest
+    subject: String,
+    scope: Scope,
     expiration_time: ExpirationTime,
+    /// Missing from tokens issued before logging out was possible
+    #[serde(default)]
+    issued_at: Option<ExpirationTime>,
+    /// Missing from guest tokens, and tokens issued before they were included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
 }
 
 impl AuthenticatedUserToken {
     /// Creates a new authenticated user token with an expiration time
-    pub fn new(email: &dyn User, expiration_time: ExpirationTime) -> Self {
+    pub fn new(subject: &str, scope: Scope, expiration_time: ExpirationTime) -> Self {
         Self {
-            email: email.email().to_string(),
+            subject: subject.to_string(),
+            scope,
             expiration_time,
+            issued_at: Some(ExpirationTime::from(SystemTime::now())),
+            user_id: None,
+            username: None,
         }
     }
 
     /// Creates a new authenticated user token that expires after a set time.
-    pub fn with_valid_duration(email: &dyn User, expires_after: Duration) -> Self {
+    pub fn with_valid_duration(user: &PublicUser, expires_after: Duration) -> Self {
         Self {
-            email: email.email().to_string(),
-            expiration_time: ExpirationTime::from(SystemTime::now()) + expires_after,
+            user_id: Some(user.id()),
+            username: Some(user.username().to_string()),
+            ..Self::new(
+                user.email().as_ref(),
+                Scope::User,
+                ExpirationTime::from(SystemTime::now()) + expires_after,
+            )
         }
     }
 
-    pub fn email(&self) -> &str {
-        &self.email
+    /// Creates a new guest token that expires after a set time.
+    pub fn guest(guest_id: &str, expires_after: Duration) -> Self {
+        Self::new(
+            guest_id,
+            Scope::Guest,
+            ExpirationTime::from(SystemTime::now()) + expires_after,
+        )
+    }
+
+    /// The email of the user, or the id of the guest, this token was issued to
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+    pub fn scope(&self) -> Scope {
+        self.scope
     }
     pub fn expiration_time(&self) -> ExpirationTime {
         self.expiration_time
     }
+    pub fn issued_at(&self) -> Option<ExpirationTime> {
+        self.issued_at
+    }
+
+    /// The claims of this token, as returned to other services
+    pub fn claims(&self) -> Claims {
+        Claims {
+            subject: self.subject.clone(),
+            user_id: self.user_id,
+            username: self.username.clone(),
+            scopes: vec![self.scope],
+            expires: self.expiration_time,
+        }
+    }
+
+    /// Ensures this token was issued after its tokens were last revoked
+    pub fn ensure_not_revoked(&self, revoked_at: Option<ExpirationTime>) -> Result<(), AuthError> {
+        match (revoked_at, self.issued_at) {
+            (Some(revoked_at), Some(issued_at)) if issued_at >= revoked_at => Ok(()),
+            (Some(_), _) => Err(AuthError::TokenRevoked),
+            (None, _) => Ok(()),
+        }
+    }
+
+    /// Ensures this token grants the given scope
+    pub fn require_scope(&self, scope: Scope) -> Result<(), AuthError> {
+        if self.scope == scope {
+            Ok(())
+        } else {
+            Err(AuthError::MissingScope(scope))
+        }
+    }
 }
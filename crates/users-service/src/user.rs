@@ -1,14 +1,18 @@
 //! Internal user
 
-use common::repo::Repository;
+use diesel::insert_into;
 use diesel::prelude::*;
-use diesel::r2d2::ConnectionManager;
-use diesel::{insert_into, select};
-use r2d2::PooledConnection;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
 
+use crate::db::DbConnection;
+use crate::error::ServiceError;
+use crate::preferences::Preferences;
 use crate::schema::user::dsl::user;
+use chrono::{NaiveDateTime, Utc};
+use common::repo::{Page, Pageable, Repository, RepositoryError, RepositoryResult};
+use std::ops::DerefMut;
 use users_api::auth::{PasswordAuth, PasswordError};
-use users_api::{EmailAddress, User as UserTrait};
+use users_api::{EmailAddress, ExpirationTime, User as UserTrait};
 
 /// User with only public info exposed
 #[derive(Debug, Clone)]
@@ -16,84 +20,186 @@ pub struct PublicUser {
     id: i64,
     email: EmailAddress,
     username: String,
+    admin: bool,
+    banned: bool,
+    tokens_revoked_at: Option<NaiveDateTime>,
 }
 
 impl PublicUser {
+    /// Creates a user, failing with [`ServiceError::UserExists`] if the email or username is
+    /// taken.
+    ///
+    /// The unique constraints of the table settle concurrent sign-ups, so the one that loses gets
+    /// the same error as a later sign-up would.
     pub fn create_new_user(
-        conn: &mut MysqlConnection,
+        conn: &mut DbConnection,
         email: &str,
         username: &str,
         password: &str,
-    ) -> QueryResult<PublicUser> {
+    ) -> Result<PublicUser, ServiceError> {
         use crate::schema::user::dsl;
 
-        if user
-            .select(InternalUser::as_select())
-            .filter(dsl::email.eq(email))
-            .first(conn)
-            .is_ok()
-        {
-            return Err(diesel::result::Error::NotFound);
+        for identifier in [email, username] {
+            if Self::get_user(conn, identifier)?.is_some() {
+                return Err(ServiceError::UserExists(identifier.to_string()));
+            }
         }
 
-        insert_into(dsl::user)
+        let inserted = insert_into(dsl::user)
             .values((
                 dsl::username.eq(username),
                 dsl::email.eq(email),
                 dsl::password_hash.eq(password),
             ))
-            .execute(conn)?;
+            .execute(conn);
+        match inserted {
+            Ok(_) => {}
+            Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info)) => {
+                // every backend names the violated column or its index in the message
+                let taken = if info.message().contains("username") {
+                    username
+                } else {
+                    email
+                };
+                return Err(ServiceError::UserExists(taken.to_string()));
+            }
+            Err(e) => return Err(RepositoryError::from(e).into()),
+        }
 
-        Ok(Self::get_user(conn, email).expect("user should have been created"))
+        Ok(Self::get_user(conn, email)?.expect("user should have been created"))
     }
 
-    pub fn get_user(conn: &mut MysqlConnection, id: &str) -> Option<PublicUser> {
-        use crate::schema::user::dsl;
+    pub fn get_user(conn: &mut DbConnection, id: &str) -> RepositoryResult<Option<PublicUser>> {
+        let mut users = UserRepository::new(conn);
+        let internal = match users.find_by_email(id)? {
+            Some(internal) => Some(internal),
+            None => users.find_by_username(id)?,
+        };
 
-        let internal = user
-            .filter(dsl::email.eq(id))
-            .first::<InternalUser>(conn)
-            .ok()
-            .or_else(|| {
-                user
-                    .filter(dsl::username.eq(id))
-                    .first::<InternalUser>(conn)
-                    .ok()
-            })
-            ?;
-
-        Some(PublicUser {
-            id: internal.id,
-            email: EmailAddress::new_unchecked(internal.email),
-            username: internal.username,
-        })
+        Ok(internal.map(PublicUser::from))
+    }
+
+    /// Gets a page of users
+    pub fn list(
+        conn: &mut DbConnection,
+        pageable: &Pageable<i64>,
+    ) -> RepositoryResult<Page<PublicUser, i64>> {
+        Ok(UserRepository::new(conn)
+            .find_all(pageable)?
+            .map(PublicUser::from))
     }
 
     pub fn verify_password(
         &self,
-        conn: &mut MysqlConnection,
+        conn: &mut DbConnection,
         auth: &PasswordAuth,
         pass: &str,
-    ) -> Result<(), PasswordError> {
-        use crate::schema::user::dsl;
-        let hashed: String = user
-            .select(dsl::password_hash)
-            .find(self.id)
-            .first(conn)
-            .map_err(|_| PasswordError::NoPasswordFound)?;
+    ) -> Result<(), ServiceError> {
+        let internal = UserRepository::new(conn)
+            .find_by_id(self.id)?
+            .ok_or(PasswordError::NoPasswordFound)?;
+
+        Ok(auth.verify_password(pass.as_bytes(), &internal.password_hash)?)
+    }
+
+    /// Gets the preferences of this user
+    pub fn preferences(&self, conn: &mut DbConnection) -> RepositoryResult<Preferences> {
+        let internal = UserRepository::new(conn)
+            .find_by_id(self.id)?
+            .ok_or(diesel::result::Error::NotFound)?;
+
+        Ok(Preferences::from_column(internal.preferences.as_deref()))
+    }
+
+    /// Replaces the preferences of this user
+    pub fn set_preferences(
+        &self,
+        conn: &mut DbConnection,
+        preferences: &Preferences,
+    ) -> RepositoryResult<()> {
+        self.update(conn, |internal| {
+            internal.preferences = Some(preferences.to_column())
+        })
+    }
+
+    /// Replaces the password of this user with an already hashed one
+    pub fn set_password_hash(&self, conn: &mut DbConnection, hash: &str) -> RepositoryResult<()> {
+        self.update(conn, |internal| internal.password_hash = hash.to_string())
+    }
+
+    /// Makes this user an admin, or takes it away
+    pub fn set_admin(&mut self, conn: &mut DbConnection, admin: bool) -> RepositoryResult<()> {
+        self.update(conn, |internal| internal.admin = admin)?;
+        self.admin = admin;
+        Ok(())
+    }
+
+    /// Bans this user from logging in, or lifts the ban
+    pub fn set_banned(&mut self, conn: &mut DbConnection, banned: bool) -> RepositoryResult<()> {
+        self.update(conn, |internal| internal.banned = banned)?;
+        self.banned = banned;
+        Ok(())
+    }
 
-        auth.verify_password(pass.as_bytes(), &hashed)
+    /// Revokes every token issued to this user until now
+    pub fn revoke_tokens(&mut self, conn: &mut DbConnection) -> RepositoryResult<()> {
+        let now = Utc::now().naive_utc();
+        self.update(conn, |internal| internal.tokens_revoked_at = Some(now))?;
+        self.set_tokens_revoked_at(now);
+        Ok(())
+    }
+
+    fn update(
+        &self,
+        conn: &mut DbConnection,
+        change: impl FnOnce(&mut InternalUser),
+    ) -> RepositoryResult<()> {
+        let mut users = UserRepository::new(conn);
+        let mut internal = users
+            .find_by_id(self.id)?
+            .ok_or(diesel::result::Error::NotFound)?;
+        change(&mut internal);
+        users.save(internal)?;
+        Ok(())
     }
 }
 
 impl PublicUser {
     pub fn new(email: EmailAddress, username: String) -> Self {
+        Self::with_id(0, email, username)
+    }
+
+    pub(crate) fn with_id(id: i64, email: EmailAddress, username: String) -> Self {
         Self {
-            id: 0,
+            id,
             email,
             username,
+            admin: false,
+            banned: false,
+            tokens_revoked_at: None,
         }
     }
+
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.admin
+    }
+
+    pub fn is_banned(&self) -> bool {
+        self.banned
+    }
+
+    /// When every token issued to this user until then was revoked
+    pub fn tokens_revoked_at(&self) -> Option<ExpirationTime> {
+        self.tokens_revoked_at.map(|at| at.and_utc())
+    }
+
+    pub(crate) fn set_tokens_revoked_at(&mut self, at: NaiveDateTime) {
+        self.tokens_revoked_at = Some(at);
+    }
 }
 
 impl UserTrait for PublicUser {
@@ -101,20 +207,62 @@ impl UserTrait for PublicUser {
         &self.username
     }
 
-    fn set_username(&mut self, name: &str) {
-        self.username = name.to_string();
-    }
-
     fn email(&self) -> EmailAddress {
         self.email.clone()
     }
 }
 
-#[derive(Queryable, Selectable, Identifiable)]
-#[diesel(table_name = crate::schema::user)]
+impl From<InternalUser> for PublicUser {
+    fn from(value: InternalUser) -> Self {
+        Self {
+            id: value.id,
+            email: EmailAddress::new_unchecked(value.email),
+            username: value.username,
+            admin: value.admin,
+            banned: value.banned,
+            tokens_revoked_at: value.tokens_revoked_at,
+        }
+    }
+}
+
+/// A user as it's stored in the database
+#[derive(Queryable, Selectable, Identifiable, Insertable, AsChangeset)]
+#[diesel(table_name = crate::schema::user, treat_none_as_null = true)]
 struct InternalUser {
     id: i64,
-    username: String,
     email: String,
+    username: String,
     password_hash: String,
+    preferences: Option<String>,
+    admin: bool,
+    banned: bool,
+    tokens_revoked_at: Option<NaiveDateTime>,
+}
+
+common::diesel_repository! {
+    /// Stores users
+    struct UserRepository<DbConnection>: InternalUser, i64 => crate::schema::user,
+        sort: [id, email, username];
+}
+
+impl<C: DerefMut<Target = DbConnection>> UserRepository<C> {
+    fn find_by_email(&mut self, email: &str) -> RepositoryResult<Option<InternalUser>> {
+        use crate::schema::user::dsl;
+        let found = user
+            .select(InternalUser::as_select())
+            .filter(dsl::email.eq(email))
+            .first(self.connection())
+            .optional()?;
+        Ok(found)
+    }
+
+    fn find_by_username(&mut self, username: &str) -> RepositoryResult<Option<InternalUser>> {
+        use crate::schema::user::dsl;
+        let found = user
+            .select(InternalUser::as_select())
+            .filter(dsl::username.eq(username))
+            .first(self.connection())
+            .optional()?;
+        Ok(found)
+    }
 }
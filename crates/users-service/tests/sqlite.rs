@@ -0,0 +1,310 @@
+//! Runs the users service against an embedded sqlite database.
+//!
+//! Run with `cargo test -p users-service --no-default-features --features sqlite`
+#![cfg(feature = "sqlite")]
+
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::http::StatusCode;
+use actix_web::web::Data;
+use actix_web::{test, App};
+use chrono::Duration;
+use common::health::Health;
+use common::repo::{AsyncRepository, Pageable};
+use common::utils::encode_base64;
+use diesel::r2d2::{ConnectionManager, Pool};
+use serde_json::json;
+use users_api::auth::PasswordAuth;
+use users_service::authenticator::Authenticator;
+use users_service::db::{connect, DatabaseRouter, DbConnection};
+use users_service::error::ServiceError;
+use users_service::guest::{AsyncGuestRepository, Guest};
+use users_service::health::{DatabaseCheck, MigrationCheck};
+use users_service::migrate::{self, MigrateError};
+use users_service::store::{store_data, DieselUserStore, UserStore};
+use users_service::user::PublicUser;
+
+#[actix_web::test]
+async fn create_and_log_in_user() {
+    let dir = tempfile::tempdir().unwrap();
+    let pool = connect(dir.path().join("users.db").to_str().unwrap());
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(Authenticator::<PublicUser>::new(b"password")))
+            .app_data(Data::new(PasswordAuth::new()))
+            .app_data(store_data(DieselUserStore::new(pool)))
+            .configure(users_service::configure),
+    )
+    .await;
+
+    let req = test::TestRequest::post()
+        .uri("/user/create")
+        .set_json(json!({
+            "email": "test@example.com",
+            "username": "test",
+            "password": "password"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(
+        resp.status().is_success(),
+        "couldn't create user: {:?}",
+        resp.status()
+    );
+
+    let req = test::TestRequest::post()
+        .uri("/user/login")
+        .insert_header((
+            AUTHORIZATION,
+            format!("Basic {}", encode_base64(&"test:password")),
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(
+        resp.status().is_success(),
+        "couldn't log in: {:?}",
+        resp.status()
+    );
+    let bearer = resp.headers().get(AUTHORIZATION).unwrap().clone();
+
+    let req = test::TestRequest::get()
+        .uri("/")
+        .insert_header((AUTHORIZATION, bearer))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(
+        resp.status().is_success(),
+        "token was not valid: {:?}",
+        resp.status()
+    );
+}
+
+#[actix_web::test]
+async fn concurrent_sign_ups_conflict() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = DieselUserStore::new(connect(dir.path().join("users.db").to_str().unwrap()));
+
+    let results = std::thread::scope(|scope| {
+        let sign_ups: Vec<_> = (0..8)
+            .map(|i| {
+                let store = &store;
+                scope.spawn(move || {
+                    store.create_user("test@example.com", &format!("test{i}"), "hash")
+                })
+            })
+            .collect();
+        sign_ups
+            .into_iter()
+            .map(|sign_up| sign_up.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    for error in results.into_iter().filter_map(Result::err) {
+        assert!(
+            matches!(&error, ServiceError::UserExists(taken) if taken == "test@example.com"),
+            "{error:?}"
+        );
+    }
+}
+
+#[actix_web::test]
+async fn page_through_guests() {
+    let dir = tempfile::tempdir().unwrap();
+    let pool = connect(dir.path().join("users.db").to_str().unwrap());
+    {
+        let mut conn = pool.get().unwrap();
+        for _ in 0..5 {
+            Guest::create_new_guest(&mut conn, Duration::hours(1)).unwrap();
+        }
+    }
+    let guests = AsyncGuestRepository::new(pool);
+
+    let first = guests.find_all(Pageable::first(2)).await.unwrap();
+    assert_eq!(first.total(), 5);
+    assert_eq!(first.content().len(), 2);
+    let second = guests
+        .find_all(first.next().unwrap().clone())
+        .await
+        .unwrap();
+    assert_eq!(second.content().len(), 2);
+    let last = guests
+        .find_all(second.next().unwrap().clone())
+        .await
+        .unwrap();
+    assert_eq!(last.content().len(), 1);
+    assert!(last.is_last());
+
+    let mut seen = vec![];
+    let mut pageable = Pageable::after(String::new(), 2);
+    loop {
+        let page = guests.find_all(pageable).await.unwrap();
+        seen.extend(page.content().iter().map(|guest| guest.id().to_string()));
+        match page.next() {
+            Some(next) => pageable = next.clone(),
+            None => break,
+        }
+    }
+    assert_eq!(seen.len(), 5);
+    assert!(
+        seen.windows(2).all(|ids| ids[0] < ids[1]),
+        "cursor pages out of order"
+    );
+    assert!(guests.exists_by_id(seen[0].clone()).await.unwrap());
+}
+
+#[actix_web::test]
+async fn reads_go_to_replica_outside_write_window() {
+    let dir = tempfile::tempdir().unwrap();
+    let primary = connect(dir.path().join("primary.db").to_str().unwrap());
+    // an empty database stands in for a replica that hasn't caught up yet
+    let replica = connect(dir.path().join("replica.db").to_str().unwrap());
+
+    DieselUserStore::new(primary.clone())
+        .create_user("old@example.com", "old", "hash")
+        .unwrap();
+    let sticky =
+        DieselUserStore::new(DatabaseRouter::new(primary.clone()).with_replica(replica.clone()));
+    sticky
+        .create_user("test@example.com", "test", "hash")
+        .unwrap();
+    // only the reads of the user written stay on the primary, by either identifier
+    assert!(sticky.get_user("test").unwrap().is_some());
+    assert!(sticky.get_user("test@example.com").unwrap().is_some());
+    assert!(sticky.get_user("old").unwrap().is_none());
+    let guest = sticky.create_guest(Duration::hours(1)).unwrap();
+    assert!(sticky.get_guest(guest.id()).unwrap().is_some());
+
+    let lagging = DieselUserStore::new(
+        DatabaseRouter::new(primary)
+            .with_replica(replica)
+            .with_read_your_writes_window(std::time::Duration::ZERO),
+    );
+    lagging.create_guest(Duration::hours(1)).unwrap();
+    assert!(lagging.get_user("test").unwrap().is_none());
+}
+
+#[actix_web::test]
+async fn reads_fall_back_to_primary_without_waiting_on_replica() {
+    let dir = tempfile::tempdir().unwrap();
+    let primary = connect(dir.path().join("primary.db").to_str().unwrap());
+    // the replica's directory doesn't exist, so no connection to it can be made
+    let replica = Pool::builder()
+        .min_idle(Some(0))
+        .connection_timeout(std::time::Duration::from_secs(30))
+        .build_unchecked(ConnectionManager::<DbConnection>::new(
+            dir.path().join("down").join("replica.db").to_str().unwrap(),
+        ));
+
+    let store = DieselUserStore::new(
+        DatabaseRouter::new(primary)
+            .with_replica(replica)
+            .with_read_your_writes_window(std::time::Duration::ZERO),
+    );
+    store
+        .create_user("test@example.com", "test", "hash")
+        .unwrap();
+    let started = std::time::Instant::now();
+    assert!(store.get_user("test").unwrap().is_some());
+    assert!(
+        started.elapsed() < std::time::Duration::from_secs(5),
+        "waited {:?} on the replica",
+        started.elapsed()
+    );
+}
+
+#[actix_web::test]
+async fn ready_once_migrated() {
+    let dir = tempfile::tempdir().unwrap();
+    let pool = connect(dir.path().join("users.db").to_str().unwrap());
+
+    let health = Health::new()
+        .with_check(DatabaseCheck(DatabaseRouter::new(pool.clone())))
+        .with_check(MigrationCheck(pool));
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(health))
+            .configure(common::health::configure),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/readyz").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success(), "not ready: {:?}", resp.status());
+}
+
+#[actix_web::test]
+async fn migrate_down_and_back_up() {
+    let dir = tempfile::tempdir().unwrap();
+    let pool = connect(dir.path().join("users.db").to_str().unwrap());
+    let mut conn = pool.get().unwrap();
+
+    migrate::ensure_up_to_date(&mut conn).unwrap();
+    let reverted = migrate::down(&mut conn).unwrap();
+    assert!(matches!(
+        migrate::ensure_up_to_date(&mut conn),
+        Err(MigrateError::Pending(pending)) if pending.len() == 1
+    ));
+    assert_eq!(migrate::up(&mut conn).unwrap(), vec![reverted]);
+    migrate::ensure_up_to_date(&mut conn).unwrap();
+}
+
+#[actix_web::test]
+async fn banned_users_cannot_log_in() {
+    let dir = tempfile::tempdir().unwrap();
+    let pool = connect(dir.path().join("users.db").to_str().unwrap());
+    let passwords = PasswordAuth::new();
+    let mut user = {
+        let mut conn = pool.get().unwrap();
+        let hash = passwords.hash_password(b"password").unwrap();
+        PublicUser::create_new_user(&mut conn, "test@example.com", "test", &hash).unwrap()
+    };
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(Authenticator::<PublicUser>::new(b"password")))
+            .app_data(Data::new(passwords))
+            .app_data(store_data(DieselUserStore::new(pool.clone())))
+            .configure(users_service::configure),
+    )
+    .await;
+    let log_in = || {
+        test::TestRequest::post()
+            .uri("/user/login")
+            .insert_header((
+                AUTHORIZATION,
+                format!("Basic {}", encode_base64(&"test:password")),
+            ))
+            .to_request()
+    };
+    let resp = test::call_service(&app, log_in()).await;
+    assert!(resp.status().is_success(), "{:?}", resp.status());
+    let bearer = resp.headers().get(AUTHORIZATION).unwrap().clone();
+
+    user.set_banned(&mut pool.get().unwrap(), true).unwrap();
+
+    let resp = test::call_service(&app, log_in()).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    // tokens issued before the ban are refused too
+    for uri in ["/", "/claims", "/user/me", "/user/preferences"] {
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .insert_header((AUTHORIZATION, bearer.clone()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{uri}");
+    }
+    let req = test::TestRequest::post()
+        .uri("/user/refresh")
+        .insert_header((AUTHORIZATION, bearer.clone()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    user.set_banned(&mut pool.get().unwrap(), false).unwrap();
+    let req = test::TestRequest::get()
+        .uri("/user/me")
+        .insert_header((AUTHORIZATION, bearer))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success(), "{:?}", resp.status());
+}